@@ -10,6 +10,12 @@ pub enum TursoMiniError {
     CompletionError(#[from] CompletionError),
     #[error("Corrupt databse: {0}")]
     Corrupt(String),
+    #[error("String or blob too big: {0}")]
+    TooBig(String),
+    #[error("Database is busy")]
+    Busy,
+    #[error("API misuse: {0}")]
+    Misuse(String),
 }
 
 // Q. Copy vs Clone?
@@ -21,10 +27,87 @@ pub enum TursoMiniError {
 // CompletionError variablts contain simple types
 // Hence this is stored on the stack
 pub enum CompletionError {
-    #[error("I/O error: {0}")]
+    // ErrorKind's Display is a sentence ("entity not found"), Debug is the
+    // variant name (NotFound). Show both so logs are readable and greppable
+    #[error("I/O error: {0} ({0:?})")]
     IOError(std::io::ErrorKind)
 }
 
+/// Primary result codes as defined by SQLite: https://www.sqlite.org/rescode.html
+/// The discriminants match SQLite so they can be passed through as is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultCode {
+    Ok = 0,
+    Error = 1,
+    Internal = 2,
+    Perm = 3,
+    Abort = 4,
+    Busy = 5,
+    Locked = 6,
+    NoMem = 7,
+    ReadOnly = 8,
+    Interrupt = 9,
+    IoErr = 10,
+    Corrupt = 11,
+    NotFound = 12,
+    Full = 13,
+    CantOpen = 14,
+    Protocol = 15,
+    Empty = 16,
+    Schema = 17,
+    TooBig = 18,
+    Constraint = 19,
+    Mismatch = 20,
+    Misuse = 21,
+    NoLfs = 22,
+    Auth = 23,
+    Format = 24,
+    Range = 25,
+    NotADb = 26,
+    Notice = 27,
+    Warning = 28,
+    Row = 100,
+    Done = 101,
+}
+
+// Numeric codes are part of the FFI surface
+// Never renumber an existing variant, only append new ones
+impl TursoMiniError {
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::FileExtensionError(_) => 1,
+            Self::FileLockingError(_) => 2,
+            Self::CompletionError(e) => e.code(),
+            Self::Corrupt(_) => 4,
+            Self::TooBig(_) => 5,
+            Self::Busy => 6,
+            Self::Misuse(_) => 7,
+        }
+    }
+
+    /// Closest SQLite result code for this error
+    pub fn result_code(&self) -> ResultCode {
+        match self {
+            Self::FileExtensionError(_) => ResultCode::CantOpen,
+            // SQLite reports lock failures as SQLITE_IOERR_LOCK, an IOERR extended code
+            Self::FileLockingError(_) => ResultCode::IoErr,
+            Self::CompletionError(_) => ResultCode::IoErr,
+            Self::Corrupt(_) => ResultCode::Corrupt,
+            Self::TooBig(_) => ResultCode::TooBig,
+            Self::Busy => ResultCode::Busy,
+            Self::Misuse(_) => ResultCode::Misuse,
+        }
+    }
+}
+
+impl CompletionError {
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::IOError(_) => 3,
+        }
+    }
+}
+
 // creting new strings is expensive in hot path
 // copying/cloning enums is cheaper
 // hence we only propagate ErrorKind
@@ -39,4 +122,65 @@ macro_rules! bail_corrupt_error {
     ($($arg:tt)*) => {
         return Err(TursoMiniError::Corrupt(format!($($arg)*)))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::{CompletionError, ResultCode, TursoMiniError};
+
+    #[test]
+    fn test_completion_error_display() {
+        let err = CompletionError::IOError(ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "I/O error: entity not found (NotFound)");
+
+        let err: TursoMiniError = std::io::Error::from(ErrorKind::PermissionDenied).into();
+        assert_eq!(
+            err.to_string(),
+            "Completion error: I/O error: permission denied (PermissionDenied)"
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = TursoMiniError::Corrupt("bad page".to_string());
+        assert_eq!(err.to_string(), "Corrupt databse: bad page");
+        let err = TursoMiniError::FileLockingError("held".to_string());
+        assert_eq!(err.to_string(), "File Locking error: held");
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(TursoMiniError::FileExtensionError(String::new()).code(), 1);
+        assert_eq!(TursoMiniError::FileLockingError(String::new()).code(), 2);
+        assert_eq!(CompletionError::IOError(ErrorKind::Other).code(), 3);
+        assert_eq!(
+            TursoMiniError::CompletionError(CompletionError::IOError(ErrorKind::Other)).code(),
+            3
+        );
+        assert_eq!(TursoMiniError::Corrupt(String::new()).code(), 4);
+        assert_eq!(TursoMiniError::TooBig(String::new()).code(), 5);
+        assert_eq!(TursoMiniError::Busy.code(), 6);
+        assert_eq!(TursoMiniError::Misuse(String::new()).code(), 7);
+    }
+
+    #[test]
+    fn test_result_codes() {
+        let err = TursoMiniError::Corrupt("bad page".to_string());
+        assert_eq!(err.result_code(), ResultCode::Corrupt);
+        assert_eq!(err.result_code() as i32, 11);
+
+        let err: TursoMiniError = std::io::Error::from(ErrorKind::UnexpectedEof).into();
+        assert_eq!(err.result_code(), ResultCode::IoErr);
+        assert_eq!(err.result_code() as i32, 10);
+
+        assert_eq!(TursoMiniError::TooBig(String::new()).result_code(), ResultCode::TooBig);
+        assert_eq!(TursoMiniError::Busy.result_code(), ResultCode::Busy);
+        assert_eq!(TursoMiniError::Misuse(String::new()).result_code(), ResultCode::Misuse);
+        assert_eq!(
+            TursoMiniError::FileExtensionError(String::new()).result_code(),
+            ResultCode::CantOpen
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use std::pin::Pin;
 use std::fmt;
+use std::ops::Range;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
 
 pub type BufferData = Pin<Box<[u8]>>;
 pub enum Buffer {
@@ -43,6 +46,30 @@ impl Buffer {
         unsafe {std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len())}
     }
 
+    pub fn fill(&self, byte: u8) {
+        self.as_mut_slice().fill(byte);
+    }
+
+    // Panics if the range is reversed or reaches past the end of the buffer
+    pub fn fill_range(&self, range: Range<usize>, byte: u8) {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "fill range {range:?} out of bounds for buffer of length {}",
+            self.len()
+        );
+        self.as_mut_slice()[range].fill(byte);
+    }
+
+    // Split the buffer into page sized slices, the last one may be shorter
+    // Useful when a multi page region was read into a single buffer
+    pub fn chunks(&self, page_size: usize) -> impl Iterator<Item = &[u8]> {
+        self.as_slice().chunks(page_size)
+    }
+
+    pub fn hexdump(&self) -> String {
+        hexdump(self.as_slice())
+    }
+
     pub fn as_ptr(&self) -> *const u8 {
         match self {
             Self::Heap(buf) => buf.as_ptr()
@@ -64,10 +91,312 @@ impl fmt::Debug for Buffer {
     }
 }
 
+/// Formats bytes like `hexdump -C`: offset, 16 hex bytes split in two groups
+/// of 8, then the printable ASCII characters ('.' for the rest), one line per 16 bytes
+/// e.g. 00000000  53 51 4c 69 74 65 20 66  6f 72 6d 61 74 20 33 00  |SQLite format 3.|
+pub fn hexdump(data: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    for (line_no, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", line_no * 16);
+        for i in 0..16 {
+            if i == 8 {
+                out.push(' ');
+            }
+            match line.get(i) {
+                Some(b) => {
+                    let _ = write!(out, " {b:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push_str("  |");
+        out.extend(line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }));
+        out.push_str("|\n");
+    }
+    out
+}
+
+/// A byte range of a shared buffer, keeps the buffer alive without copying it
+#[derive(Debug, Clone)]
+pub struct BufferSlice {
+    buffer: Arc<Buffer>,
+    range: Range<usize>,
+}
+
+impl BufferSlice {
+    // Panics if the range is reversed or reaches past the end of the buffer
+    pub fn new(buffer: Arc<Buffer>, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= buffer.len(),
+            "slice range {range:?} out of bounds for buffer of length {}",
+            buffer.len()
+        );
+        Self { buffer, range }
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buffer.as_slice()[self.range.clone()]
+    }
+
+    /// Splits into [0, mid) and [mid, len), both sharing the same buffer
+    /// Panics if mid > len
+    pub fn split_at(&self, mid: usize) -> (BufferSlice, BufferSlice) {
+        assert!(mid <= self.len(), "split point {mid} past slice length {}", self.len());
+        let mid = self.range.start + mid;
+        (
+            Self { buffer: self.buffer.clone(), range: self.range.start..mid },
+            Self { buffer: self.buffer.clone(), range: mid..self.range.end },
+        )
+    }
+}
+
+/// Cursor over a shared buffer so byte oriented parsers can consume a page
+pub struct BufferReader {
+    buffer: Arc<Buffer>,
+    pos: u64,
+}
+
+impl BufferReader {
+    pub fn new(buffer: Arc<Buffer>) -> Self {
+        Self { buffer, pos: 0 }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+}
+
+impl Read for BufferReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.buffer.as_slice();
+        // reading at or past the end is EOF, not an error
+        let start = (self.pos as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for BufferReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => (self.buffer.len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        match base.checked_add_signed(offset) {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Cursor for assembling a buffer incrementally
+/// Writing past the end fails with WriteZero instead of panicking
+pub struct BufferWriter {
+    buffer: Arc<Buffer>,
+    pos: usize,
+}
+
+impl BufferWriter {
+    pub fn new(buffer: Arc<Buffer>) -> Self {
+        Self { buffer, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn into_inner(self) -> Arc<Buffer> {
+        self.buffer
+    }
+}
+
+impl Write for BufferWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let data = self.buffer.as_mut_slice();
+        let n = buf.len().min(data.len() - self.pos);
+        if n == 0 && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                "buffer is full",
+            ));
+        }
+        data[self.pos..self.pos + n].copy_from_slice(&buf[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 // Rust will handle cleanup automatically
 // The Arc<Buffer> will automatically deallocates when ref counts reaches 0
 // So let's not implement Drop trait for now
 // impl Drop for Buffer {
 //     fn drop(&mut self) {
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+    use std::sync::Arc;
+
+    use super::{Buffer, BufferReader, BufferSlice, BufferWriter};
+
+    #[test]
+    fn test_hexdump() {
+        let buf = Buffer::new(b"SQLite format 3\0\x10\x00\x01".to_vec());
+        let dump = buf.hexdump();
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "00000000  53 51 4c 69 74 65 20 66  6f 72 6d 61 74 20 33 00  |SQLite format 3.|"
+        );
+        assert_eq!(
+            lines[1],
+            "00000010  10 00 01                                          |...|"
+        );
+        assert_eq!(Buffer::new_zeroed(0).hexdump(), "");
+    }
+
+    #[test]
+    fn test_fill() {
+        let buf = Buffer::new(vec![1; 8]);
+        buf.fill(0xab);
+        assert_eq!(buf.as_slice(), &[0xab; 8]);
+
+        buf.fill_range(2..5, 0);
+        assert_eq!(buf.as_slice(), &[0xab, 0xab, 0, 0, 0, 0xab, 0xab, 0xab]);
+        buf.fill_range(8..8, 1);
+        assert_eq!(buf.as_slice()[7], 0xab);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn test_fill_range_past_end() {
+        Buffer::new_zeroed(8).fill_range(4..9, 1);
+    }
+
+    #[test]
+    fn test_chunks_with_partial_last_page() {
+        let buf = Buffer::new_zeroed(10 * 1024);
+        let sizes: Vec<usize> = buf.chunks(4096).map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![4096, 4096, 2048]);
+    }
+
+    #[test]
+    fn test_chunks_do_not_overlap() {
+        let data: Vec<u8> = (0..12).collect();
+        let buf = Buffer::new(data);
+        let chunks: Vec<&[u8]> = buf.chunks(4).collect();
+        assert_eq!(chunks, vec![&[0, 1, 2, 3][..], &[4, 5, 6, 7], &[8, 9, 10, 11]]);
+    }
+
+    #[test]
+    fn test_buffer_slice_split_at() {
+        let buffer = Arc::new(Buffer::new((0..10).collect()));
+        let slice = BufferSlice::new(buffer.clone(), 2..9);
+        let (head, tail) = slice.split_at(3);
+        assert_eq!(head.as_slice(), &[2, 3, 4]);
+        assert_eq!(tail.as_slice(), &[5, 6, 7, 8]);
+
+        let (empty, all) = tail.split_at(0);
+        assert!(empty.is_empty());
+        assert_eq!(all.len(), 4);
+
+        // the halves keep the buffer alive
+        drop(slice);
+        drop(buffer);
+        assert_eq!(head.as_slice(), &[2, 3, 4]);
+        assert_eq!(Arc::strong_count(&tail.buffer), 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "past slice length")]
+    fn test_buffer_slice_split_past_end() {
+        BufferSlice::new(Arc::new(Buffer::new_zeroed(4)), 0..4).split_at(5);
+    }
+
+    #[test]
+    fn test_buffer_reader_read_and_seek() {
+        let data: Vec<u8> = (0..32).collect();
+        let mut reader = BufferReader::new(Arc::new(Buffer::new(data)));
+
+        let mut out = [0u8; 10];
+        reader.read_exact(&mut out).unwrap();
+        assert_eq!(out, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(reader.position(), 10);
+
+        assert_eq!(reader.seek(SeekFrom::Start(20)).unwrap(), 20);
+        reader.read_exact(&mut out[..4]).unwrap();
+        assert_eq!(&out[..4], &[20, 21, 22, 23]);
+
+        assert_eq!(reader.seek(SeekFrom::Current(-14)).unwrap(), 10);
+        assert_eq!(reader.seek(SeekFrom::End(-2)).unwrap(), 30);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, vec![30, 31]);
+    }
+
+    #[test]
+    fn test_buffer_reader_eof_and_invalid_seek() {
+        let mut reader = BufferReader::new(Arc::new(Buffer::new(vec![1, 2, 3])));
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        let mut out = [0u8; 4];
+        assert_eq!(reader.read(&mut out).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Current(-20)).is_err());
+        assert_eq!(reader.position(), 10);
+    }
+
+    #[test]
+    fn test_buffer_writer() {
+        let buffer = Arc::new(Buffer::new_zeroed(8));
+        let mut writer = BufferWriter::new(buffer.clone());
+        writer.write_all(&[1, 2, 3]).unwrap();
+        writer.write_all(&[4, 5]).unwrap();
+        assert_eq!(writer.position(), 5);
+        assert_eq!(buffer.as_slice(), &[1, 2, 3, 4, 5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_buffer_writer_past_end() {
+        let buffer = Arc::new(Buffer::new_zeroed(4));
+        let mut writer = BufferWriter::new(buffer.clone());
+
+        // partial write fills what fits
+        assert_eq!(writer.write(&[9; 6]).unwrap(), 4);
+        let err = writer.write(&[1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+
+        let mut writer = BufferWriter::new(Arc::new(Buffer::new_zeroed(4)));
+        let err = writer.write_all(&[7; 5]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        assert_eq!(writer.into_inner().as_slice(), &[7, 7, 7, 7]);
+        assert_eq!(buffer.as_slice(), &[9, 9, 9, 9]);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{cell::Cell, rc::Rc, sync::Arc};
+
+use crate::{bail_corrupt_error, error::TursoMiniError, Result};
+use crate::io::{check_page_aligned, error::CompletionError, Buffer, Completion, File};
+
+pub const CHECKSUM_BYTES: usize = 8;
+// Offset of the "reserved space per page" byte in the database header
+const RESERVED_SPACE_OFFSET: usize = 20;
+
+/// Checksum used by SQLite's cksumvfs extension: https://sqlite.org/cksumvfs.html
+/// A Fletcher style pair of sums over the data read as little endian u32s,
+/// stored as two little endian u32s
+pub fn compute_checksum(data: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let mut s1: u32 = 0;
+    let mut s2: u32 = 0;
+    for pair in data.chunks_exact(8) {
+        let a = u32::from_le_bytes([pair[0], pair[1], pair[2], pair[3]]);
+        let b = u32::from_le_bytes([pair[4], pair[5], pair[6], pair[7]]);
+        s1 = s1.wrapping_add(a).wrapping_add(s2);
+        s2 = s2.wrapping_add(b).wrapping_add(s1);
+    }
+    let mut out = [0; CHECKSUM_BYTES];
+    out[..4].copy_from_slice(&s1.to_le_bytes());
+    out[4..].copy_from_slice(&s2.to_le_bytes());
+    out
+}
+
+fn page_checksum_matches(page: &[u8]) -> bool {
+    let (data, trailer) = page.split_at(page.len() - CHECKSUM_BYTES);
+    compute_checksum(data) == trailer
+}
+
+/// File decorator storing a checksum in the last 8 bytes of every page,
+/// compatible with SQLite's cksumvfs
+///
+/// The database must be created with 8 reserved bytes per page (header byte 20)
+/// so the b-tree layer never uses the trailer. Writes of page 1 are rejected otherwise.
+/// Reads and writes must be page aligned and cover whole pages.
+pub struct ChecksumFile<F: File> {
+    inner: F,
+    page_size: usize,
+}
+
+impl<F: File> ChecksumFile<F> {
+    pub fn new(inner: F, page_size: usize) -> Self {
+        Self { inner, page_size }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    fn fill_checksums(&self, pos: u64, buffer: &Buffer) -> Result<()> {
+        check_page_aligned(pos, buffer.len(), self.page_size)?;
+        let data = buffer.as_mut_slice();
+        if pos == 0 && data.len() > RESERVED_SPACE_OFFSET && data[RESERVED_SPACE_OFFSET] as usize != CHECKSUM_BYTES {
+            bail_corrupt_error!(
+                "checksums need {CHECKSUM_BYTES} reserved bytes per page, header has {}",
+                data[RESERVED_SPACE_OFFSET]
+            );
+        }
+        for page in data.chunks_exact_mut(self.page_size) {
+            let (body, trailer) = page.split_at_mut(self.page_size - CHECKSUM_BYTES);
+            trailer.copy_from_slice(&compute_checksum(body));
+        }
+        Ok(())
+    }
+}
+
+impl<F: File> File for ChecksumFile<F> {
+    fn lock_file(&self) -> Result<()> {
+        self.inner.lock_file()
+    }
+
+    fn unlock_file(&self) -> Result<()> {
+        self.inner.unlock_file()
+    }
+
+    /// Returns the caller's completion, which completes once the checksums are verified
+    /// On a mismatch it fails with InvalidData
+    /// If the inner file completed the read synchronously, Corrupt is also returned
+    fn pread(&self, pos: u64, c: Completion) -> Result<Completion> {
+        let buf = c.as_read().buf.clone();
+        check_page_aligned(pos, buf.len(), self.page_size)?;
+
+        let page_size = self.page_size;
+        let mismatch = Rc::new(Cell::new(false));
+        let inner_c = {
+            let mismatch = mismatch.clone();
+            let c = c.clone();
+            Completion::new_read(buf, move |res| match res {
+                Ok((buf, n)) => {
+                    // pages past the end of file were not read, nothing to verify
+                    let read = &buf.as_slice()[..(n.max(0) as usize / page_size) * page_size];
+                    if read.chunks_exact(page_size).all(page_checksum_matches) {
+                        c.complete(n);
+                    } else {
+                        mismatch.set(true);
+                        c.error(CompletionError::IOError(std::io::ErrorKind::InvalidData));
+                    }
+                }
+                Err(e) => c.error(e),
+            })
+        };
+        self.inner.pread(pos, inner_c)?;
+        if mismatch.get() {
+            bail_corrupt_error!("page checksum mismatch reading at offset {pos}");
+        }
+        Ok(c)
+    }
+
+    fn pwrite(&self, pos: u64, buffer: Arc<Buffer>, c: Completion) -> Result<Completion> {
+        self.fill_checksums(pos, &buffer)?;
+        self.inner.pwrite(pos, buffer, c)
+    }
+
+    fn sync(&self, c: Completion) -> Result<Completion> {
+        self.inner.sync(c)
+    }
+
+    fn truncate(&self, len: u64, c: Completion) -> Result<Completion> {
+        self.inner.truncate(len, c)
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.inner.size()
+    }
+
+    fn pwritev(&self, pos: u64, buffers: Vec<Arc<Buffer>>, c: Completion) -> Result<Completion> {
+        let mut offset = pos;
+        for buffer in &buffers {
+            self.fill_checksums(offset, buffer)?;
+            offset += buffer.len() as u64;
+        }
+        self.inner.pwritev(pos, buffers, c)
+    }
+
+    fn sector_size(&self) -> usize {
+        self.inner.sector_size()
+    }
+
+    fn supports_atomic_writes(&self) -> bool {
+        self.inner.supports_atomic_writes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc, sync::Arc};
+
+    use super::{compute_checksum, ChecksumFile, CHECKSUM_BYTES, RESERVED_SPACE_OFFSET};
+    use crate::error::TursoMiniError;
+    use crate::io::{memory::MemoryFile, Buffer, Completion, File};
+
+    const PAGE_SIZE: usize = 512;
+
+    fn page(fill: u8) -> Arc<Buffer> {
+        let mut data = vec![fill; PAGE_SIZE];
+        data[RESERVED_SPACE_OFFSET] = CHECKSUM_BYTES as u8;
+        Arc::new(Buffer::new(data))
+    }
+
+    fn read_page(file: &dyn File, pos: u64) -> (Result<(), TursoMiniError>, Option<bool>) {
+        let ok = Rc::new(Cell::new(None));
+        let c = {
+            let ok = ok.clone();
+            Completion::new_read(Arc::new(Buffer::new_zeroed(PAGE_SIZE)), move |res| ok.set(Some(res.is_ok())))
+        };
+        (file.pread(pos, c).map(|_| ()), ok.get())
+    }
+
+    #[test]
+    fn test_compute_checksum() {
+        assert_eq!(compute_checksum(&[0; 16]), [0; 8]);
+        // s1 = 1, s2 = 2 + 1 = 3
+        let data = [1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(compute_checksum(&data), [1, 0, 0, 0, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_checksum_write_and_read() {
+        let file = ChecksumFile::new(MemoryFile::new("test.db"), PAGE_SIZE);
+        file.pwrite(0, page(1), Completion::noop_write()).unwrap();
+        file.pwritev(PAGE_SIZE as u64, vec![page(2), page(3)], Completion::noop_write()).unwrap();
+
+        for i in 0..3 {
+            let (res, ok) = read_page(&file, (i * PAGE_SIZE) as u64);
+            assert!(res.is_ok());
+            assert_eq!(ok, Some(true));
+        }
+
+        // trailer holds the checksum of the rest of the page
+        let raw = Arc::new(Buffer::new_zeroed(PAGE_SIZE));
+        file.inner().pread(0, Completion::new_read(raw.clone(), |_| {})).unwrap();
+        let (body, trailer) = raw.as_slice().split_at(PAGE_SIZE - CHECKSUM_BYTES);
+        assert_eq!(trailer, compute_checksum(body));
+    }
+
+    #[test]
+    fn test_checksum_detects_flipped_byte() {
+        let file = ChecksumFile::new(MemoryFile::new("test.db"), PAGE_SIZE);
+        file.pwrite(0, page(1), Completion::noop_write()).unwrap();
+        file.pwrite(PAGE_SIZE as u64, page(2), Completion::noop_write()).unwrap();
+
+        // flip a byte of page 2 behind the decorator's back
+        let flipped = Arc::new(Buffer::new(vec![0xFE]));
+        file.inner().pwrite(PAGE_SIZE as u64 + 100, flipped, Completion::noop_write()).unwrap();
+
+        let (res, ok) = read_page(&file, PAGE_SIZE as u64);
+        assert!(matches!(res, Err(TursoMiniError::Corrupt(_))));
+        assert_eq!(ok, Some(false));
+
+        let (res, ok) = read_page(&file, 0);
+        assert!(res.is_ok());
+        assert_eq!(ok, Some(true));
+    }
+
+    #[test]
+    fn test_checksum_requires_reserved_bytes() {
+        let file = ChecksumFile::new(MemoryFile::new("test.db"), PAGE_SIZE);
+        let no_reserved = Arc::new(Buffer::new_zeroed(PAGE_SIZE));
+        let res = file.pwrite(0, no_reserved, Completion::noop_write());
+        assert!(matches!(res, Err(TursoMiniError::Corrupt(_))));
+
+        let unaligned = file.pwrite(10, page(1), Completion::noop_write());
+        assert!(matches!(unaligned, Err(TursoMiniError::Misuse(_))));
+    }
+}
@@ -1,7 +1,8 @@
-use std::{cell::{Cell, UnsafeCell}, collections::{BTreeMap, HashMap}, sync::Mutex};
+use std::{cell::{Cell, UnsafeCell}, collections::{BTreeMap, HashMap}, rc::Rc, sync::{atomic::{AtomicBool, Ordering}, Mutex}};
 use std::sync::Arc;
 
-use crate::io::{clock::{Clock, Instant}, Buffer, Completion, File, OpenFlags, IO};
+use crate::error::TursoMiniError;
+use crate::io::{clock::{Clock, Instant}, error::CompletionError, Buffer, Completion, File, OpenFlags, IO};
 use crate::Result;
 
 const PAGE_SIZE: usize = 4096;
@@ -18,16 +19,29 @@ pub struct MemoryFile {
     path: String,
     pages: UnsafeCell<BTreeMap<usize, MemPage>>,
     size: Cell<u64>,
+    // MemoryIO hands out the same MemoryFile for a path, so a second lock_file
+    // through it sees the first connection's lock. Atomic because those
+    // connections may race for it from different threads
+    locked: AtomicBool,
 }
 
 unsafe impl Sync for MemoryFile {}
 
 impl File for MemoryFile {
     fn lock_file(&self) -> Result<()> {
+        if self.locked.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return Err(TursoMiniError::Busy);
+        }
         Ok(())
     }
 
+    /// Only the holder of the lock may call this: every handle shares the
+    /// same MemoryFile, so it can't tell who took the lock.
+    /// Unlocking a file that is not locked is Misuse
     fn unlock_file(&self) -> crate::Result<()> {
+        if self.locked.compare_exchange(true, false, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return Err(TursoMiniError::Misuse(format!("unlock of {} which is not locked", self.path)));
+        }
         Ok(())
     }
 
@@ -116,14 +130,7 @@ impl File for MemoryFile {
     }
 
     fn truncate(&self, len: u64, c: Completion) -> Result<Completion> {
-        let file_size = self.size.get();
-        if len < file_size {
-            unsafe {
-                let pages = &mut *self.pages.get();
-                pages.retain(|&k, _| k*PAGE_SIZE < len as usize);
-            }
-        }
-        self.size.set(len);
+        self.set_len(len);
         c.complete(0);
         Ok(c)
     }
@@ -143,7 +150,7 @@ impl File for MemoryFile {
                 continue;
             }
 
-            let mut remaining = offset;
+            let mut remaining = buf_len;
             let mut buf_offset = 0;
             let data = buffer.as_slice();
 
@@ -172,6 +179,33 @@ impl File for MemoryFile {
 }
 
 impl MemoryFile {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            pages: BTreeMap::new().into(),
+            size: 0.into(),
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets the logical file size without allocating pages
+    /// Growing leaves a sparse hole: reads in it return zeros and writes
+    /// allocate pages on demand. Shrinking drops the pages past the new end
+    pub fn set_len(&self, len: u64) {
+        if len < self.size.get() {
+            let len = len as usize;
+            unsafe {
+                let pages = &mut *self.pages.get();
+                pages.retain(|&k, _| k * PAGE_SIZE < len);
+                // zero the cut off tail of the last page so growing again reads zeros
+                if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                    page[len % PAGE_SIZE..].fill(0);
+                }
+            }
+        }
+        self.size.set(len);
+    }
+
     fn get_page(&self, page_no: usize) -> Option<&MemPage> {
         unsafe {(*self.pages.get()).get(&page_no)}
     }
@@ -222,13 +256,191 @@ impl IO for MemoryIO {
         if !files.contains_key(path) {
             files.insert(
                 path.to_string(),
-                Arc::new(MemoryFile{
-                    path: path.to_string(), 
-                    pages: BTreeMap::new().into(),
-                    size: 0.into(),
-                })
+                Arc::new(MemoryFile::new(path))
             );
         }
         Ok(files.get(path).unwrap().clone())
     }
-}
\ No newline at end of file
+}
+
+/// Copies the byte image of src into dst_path opened through io, creating it if needed
+/// Copy happens one memory page at a time, holes in src are written as zeros
+pub fn export_to(io: &dyn IO, src: &MemoryFile, dst_path: &str) -> Result<()> {
+    let dst = io.open_file(dst_path, OpenFlags::Create)?;
+    let size = src.size.get();
+
+    // dst may hold a longer image from an earlier export
+    let err = Rc::new(Cell::new(None));
+    let c = Completion::new_trunc(record_error(&err));
+    wait_for(io, dst.truncate(size, c)?, &err)?;
+
+    let mut pos = 0;
+    while pos < size {
+        let len = (size - pos).min(PAGE_SIZE as u64) as usize;
+        let data = match src.get_page(pos as usize / PAGE_SIZE) {
+            Some(page) => page[..len].to_vec(),
+            None => vec![0; len],
+        };
+        let c = Completion::new_write(record_error(&err));
+        wait_for(io, dst.pwrite(pos, Arc::new(Buffer::new(data)), c)?, &err)?;
+        pos += len as u64;
+    }
+
+    let c = Completion::new_sync(record_error(&err));
+    wait_for(io, dst.sync(c)?, &err)
+}
+
+/// Loads the byte image of src_path opened through io into a new MemoryFile
+pub fn import_from(io: &dyn IO, src_path: &str) -> Result<Arc<MemoryFile>> {
+    let src = io.open_file(src_path, OpenFlags::ReadOnly)?;
+    let size = src.size()?;
+    let dst = Arc::new(MemoryFile::new(src_path));
+
+    let err = Rc::new(Cell::new(None));
+    let mut pos = 0;
+    while pos < size {
+        let len = (size - pos).min(PAGE_SIZE as u64) as usize;
+        let buf = Arc::new(Buffer::new_zeroed(len));
+        let c = Completion::new_read(buf.clone(), {
+            let err = err.clone();
+            move |res| {
+                if let Err(e) = res {
+                    err.set(Some(e));
+                }
+            }
+        });
+        wait_for(io, src.pread(pos, c)?, &err)?;
+        dst.pwrite(pos, buf, Completion::noop_write())?;
+        pos += len as u64;
+    }
+    Ok(dst)
+}
+
+fn record_error(err: &Rc<Cell<Option<CompletionError>>>) -> impl Fn(Result<i32, CompletionError>) + 'static {
+    let err = err.clone();
+    move |res| {
+        if let Err(e) = res {
+            err.set(Some(e));
+        }
+    }
+}
+
+fn wait_for(io: &dyn IO, c: Completion, err: &Cell<Option<CompletionError>>) -> Result<()> {
+    if !c.is_completed() {
+        io.wait_for_completion(c)?;
+    }
+    match err.take() {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{export_to, import_from, MemoryFile, MemoryIO, PAGE_SIZE};
+    use crate::io::{Buffer, Completion, File, OpenFlags, IO};
+
+    fn read_all(file: &dyn File) -> Vec<u8> {
+        let buf = Arc::new(Buffer::new_zeroed(file.size().unwrap() as usize));
+        file.pread(0, Completion::new_read(buf.clone(), |_| {})).unwrap();
+        buf.as_slice().to_vec()
+    }
+
+    fn allocated_pages(file: &MemoryFile) -> usize {
+        unsafe { (*file.pages.get()).len() }
+    }
+
+    #[test]
+    fn test_sector_size_defaults() {
+        let file = MemoryFile::new("sector.db");
+        assert_eq!(file.sector_size(), 512);
+        assert!(!file.supports_atomic_writes());
+    }
+
+    #[test]
+    fn test_set_len_sparse_hole() {
+        let file = MemoryFile::new("sparse.db");
+        file.set_len(1 << 30);
+        assert_eq!(file.size().unwrap(), 1 << 30);
+        assert_eq!(allocated_pages(&file), 0);
+
+        let buf = Arc::new(Buffer::new(vec![0xAA; 64]));
+        file.pread(500 * PAGE_SIZE as u64, Completion::new_read(buf.clone(), |_| {})).unwrap();
+        assert_eq!(buf.as_slice(), &[0; 64]);
+
+        let pos = 1000 * PAGE_SIZE as u64 + 10;
+        file.pwrite(pos, Arc::new(Buffer::new(vec![7; 20])), Completion::noop_write()).unwrap();
+        assert_eq!(allocated_pages(&file), 1);
+        assert_eq!(file.size().unwrap(), 1 << 30);
+
+        let buf = Arc::new(Buffer::new_zeroed(30));
+        file.pread(pos - 5, Completion::new_read(buf.clone(), |_| {})).unwrap();
+        assert_eq!(&buf.as_slice()[..5], &[0; 5]);
+        assert_eq!(&buf.as_slice()[5..25], &[7; 20]);
+        assert_eq!(&buf.as_slice()[25..], &[0; 5]);
+    }
+
+    #[test]
+    fn test_set_len_shrink_then_grow_reads_zeros() {
+        let file = MemoryFile::new("shrink.db");
+        file.pwrite(0, Arc::new(Buffer::new(vec![9; 2 * PAGE_SIZE])), Completion::noop_write()).unwrap();
+        file.set_len(100);
+        assert_eq!(allocated_pages(&file), 1);
+        file.set_len(2 * PAGE_SIZE as u64);
+
+        let data = read_all(&file);
+        assert_eq!(&data[..100], &[9; 100]);
+        assert!(data[100..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let image: Vec<u8> = (0..(2 * PAGE_SIZE + 100)).map(|i| (i % 251) as u8).collect();
+        let src = MemoryFile::new("src.db");
+        src.pwrite(0, Arc::new(Buffer::new(image.clone())), Completion::noop_write()).unwrap();
+
+        let disk = MemoryIO::new();
+        export_to(&disk, &src, "exported.db").unwrap();
+        let exported = disk.open_file("exported.db", OpenFlags::ReadOnly).unwrap();
+        assert_eq!(exported.size().unwrap(), image.len() as u64);
+        assert_eq!(read_all(exported.as_ref()), image);
+
+        let imported = import_from(&disk, "exported.db").unwrap();
+        assert_eq!(imported.size().unwrap(), image.len() as u64);
+        assert_eq!(read_all(imported.as_ref()), image);
+    }
+
+    #[test]
+    fn test_export_overwrites_longer_destination() {
+        let disk = MemoryIO::new();
+        let dst = disk.open_file("dst.db", OpenFlags::Create).unwrap();
+        dst.pwrite(0, Arc::new(Buffer::new(vec![0xFF; 3 * PAGE_SIZE])), Completion::noop_write())
+            .unwrap();
+
+        let src = MemoryFile::new("src.db");
+        src.pwrite(0, Arc::new(Buffer::new(vec![7; 10])), Completion::noop_write()).unwrap();
+        export_to(&disk, &src, "dst.db").unwrap();
+
+        assert_eq!(dst.size().unwrap(), 10);
+        assert_eq!(read_all(dst.as_ref()), vec![7; 10]);
+    }
+
+    #[test]
+    fn test_pwritev_writes_each_buffer_in_full() {
+        let file = MemoryFile::new("vectored.db");
+        // at offset 0 and across the 4096 byte page boundary
+        let buffers = vec![Arc::new(Buffer::new(vec![1; 3])), Arc::new(Buffer::new(vec![2; 5]))];
+        file.pwritev(0, buffers, Completion::noop_write()).unwrap();
+        let buffers = vec![Arc::new(Buffer::new(vec![3; 100])), Arc::new(Buffer::new(vec![4; 10]))];
+        file.pwritev(4000, buffers, Completion::noop_write()).unwrap();
+
+        let data = read_all(&file);
+        assert_eq!(data.len(), 4110);
+        assert_eq!(&data[..8], &[1, 1, 1, 2, 2, 2, 2, 2]);
+        assert!(data[8..4000].iter().all(|&b| b == 0));
+        assert!(data[4000..4100].iter().all(|&b| b == 3));
+        assert!(data[4100..].iter().all(|&b| b == 4));
+    }
+}
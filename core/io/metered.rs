@@ -0,0 +1,211 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::io::{clock::{Clock, Instant}, Buffer, Completion, File, OpenFlags, IO};
+use crate::Result;
+
+/// Snapshot of the operations issued through a MeteredIO
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub syncs: u64,
+}
+
+#[derive(Default)]
+struct IoCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    syncs: AtomicU64,
+}
+
+// Counters are independent, there is no need to order them against each other
+impl IoCounters {
+    fn snapshot(&self) -> IoStats {
+        IoStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            syncs: self.syncs.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// IO decorator counting the operations issued on the files it opens
+/// Byte counts come from the completions, so they reflect what the backend
+/// actually transferred rather than what was requested
+pub struct MeteredIO<T: IO> {
+    inner: T,
+    counters: Arc<IoCounters>,
+}
+
+impl<T: IO> MeteredIO<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            counters: Arc::new(IoCounters::default()),
+        }
+    }
+
+    pub fn stats(&self) -> IoStats {
+        self.counters.snapshot()
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: IO> Clock for MeteredIO<T> {
+    fn now(&self) -> Instant {
+        self.inner.now()
+    }
+}
+
+impl<T: IO> IO for MeteredIO<T> {
+    fn open_file(&self, path: &str, flags: OpenFlags) -> Result<Arc<dyn File>> {
+        let file = self.inner.open_file(path, flags)?;
+        Ok(Arc::new(MeteredFile {
+            inner: file,
+            counters: self.counters.clone(),
+        }))
+    }
+
+    fn remove_file(&self, path: &str) -> Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn step(&self) -> Result<()> {
+        self.inner.step()
+    }
+
+    fn cancel(&self, c: &[Completion]) -> Result<()> {
+        self.inner.cancel(c)
+    }
+
+    fn drain(&self) -> Result<()> {
+        self.inner.drain()
+    }
+
+    fn wait_for_completion(&self, c: Completion) -> Result<()> {
+        self.inner.wait_for_completion(c)
+    }
+}
+
+struct MeteredFile {
+    inner: Arc<dyn File>,
+    counters: Arc<IoCounters>,
+}
+
+impl MeteredFile {
+    fn count_bytes(&self, c: &Completion, counter: fn(&IoCounters) -> &AtomicU64) {
+        let counters = self.counters.clone();
+        c.on_done(move |res| {
+            if let Ok(n) = res {
+                counter(&counters).fetch_add(n.max(0) as u64, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+impl File for MeteredFile {
+    fn lock_file(&self) -> Result<()> {
+        self.inner.lock_file()
+    }
+
+    fn unlock_file(&self) -> Result<()> {
+        self.inner.unlock_file()
+    }
+
+    fn pread(&self, pos: u64, c: Completion) -> Result<Completion> {
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
+        self.count_bytes(&c, |counters| &counters.bytes_read);
+        self.inner.pread(pos, c)
+    }
+
+    fn pwrite(&self, pos: u64, buffer: Arc<Buffer>, c: Completion) -> Result<Completion> {
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
+        self.count_bytes(&c, |counters| &counters.bytes_written);
+        self.inner.pwrite(pos, buffer, c)
+    }
+
+    fn sync(&self, c: Completion) -> Result<Completion> {
+        self.counters.syncs.fetch_add(1, Ordering::Relaxed);
+        self.inner.sync(c)
+    }
+
+    fn truncate(&self, len: u64, c: Completion) -> Result<Completion> {
+        self.inner.truncate(len, c)
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.inner.size()
+    }
+
+    fn pwritev(&self, pos: u64, buffers: Vec<Arc<Buffer>>, c: Completion) -> Result<Completion> {
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
+        self.count_bytes(&c, |counters| &counters.bytes_written);
+        self.inner.pwritev(pos, buffers, c)
+    }
+
+    fn sector_size(&self) -> usize {
+        self.inner.sector_size()
+    }
+
+    fn supports_atomic_writes(&self) -> bool {
+        self.inner.supports_atomic_writes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{IoStats, MeteredIO};
+    use crate::io::{memory::MemoryIO, Buffer, Completion, OpenFlags, IO};
+
+    #[test]
+    fn test_metered_io_counts_operations() {
+        let io = MeteredIO::new(MemoryIO::new());
+        assert_eq!(io.stats(), IoStats::default());
+
+        let file = io.open_file("test.db", OpenFlags::Create).unwrap();
+        file.pwrite(0, Arc::new(Buffer::new_zeroed(4096)), Completion::noop_write()).unwrap();
+        file.pwrite(4096, Arc::new(Buffer::new_zeroed(100)), Completion::noop_write()).unwrap();
+        file.sync(Completion::noop_sync()).unwrap();
+
+        let buf = Arc::new(Buffer::new_zeroed(512));
+        file.pread(0, Completion::new_read(buf.clone(), |_| {})).unwrap();
+        // read past the end transfers fewer bytes than requested
+        file.pread(4000, Completion::new_read(buf, |_| {})).unwrap();
+
+        assert_eq!(
+            io.stats(),
+            IoStats {
+                reads: 2,
+                writes: 2,
+                bytes_read: 512 + 196,
+                bytes_written: 4196,
+                syncs: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_metered_io_shares_counters_across_files() {
+        let io = MeteredIO::new(MemoryIO::new());
+        let a = io.open_file("a.db", OpenFlags::Create).unwrap();
+        let b = io.open_file("b.db", OpenFlags::Create).unwrap();
+        a.pwrite(0, Arc::new(Buffer::new_zeroed(10)), Completion::noop_write()).unwrap();
+        b.pwrite(0, Arc::new(Buffer::new_zeroed(20)), Completion::noop_write()).unwrap();
+
+        let stats = io.stats();
+        assert_eq!(stats.writes, 2);
+        assert_eq!(stats.bytes_written, 30);
+    }
+}
@@ -2,15 +2,20 @@ use bitflags::bitflags;
 use clock::Clock;
 use error::TursoMiniError;
 use core::fmt;
+use std::cell::RefCell;
 use std::sync::{Arc, OnceLock};
 pub use buffer::Buffer;
 use error::CompletionError;
 use std::fmt::Debug;
 
 pub mod buffer;
+pub mod checksum;
 pub mod error;
 pub mod clock;
+pub mod encrypted;
 pub mod memory;
+pub mod metered;
+pub mod retry;
 
 pub type Result<T, E = TursoMiniError> = std::result::Result<T, E>;
 
@@ -23,6 +28,55 @@ pub trait File: Send + Sync {
     fn truncate(&self, len: u64, c: Completion) -> Result<Completion>;
     fn size(&self) -> Result<u64>;
     fn pwritev(&self, pos: u64, buffers: Vec<Arc<Buffer>>, c: Completion) -> Result<Completion>;
+
+    /// Smallest unit the device writes, a torn write never splits a sector
+    fn sector_size(&self) -> usize {
+        512
+    }
+
+    /// Whether a write of up to sector_size bytes is all or nothing on a crash
+    fn supports_atomic_writes(&self) -> bool {
+        false
+    }
+
+    /// Locks the file until the returned guard is dropped
+    /// For a dyn File use FileLockGuard::new
+    fn lock_scoped(&self) -> Result<FileLockGuard<'_>>
+    where
+        Self: Sized,
+    {
+        FileLockGuard::new(self)
+    }
+}
+
+/// For page based File decorators: IO must start on a page boundary and cover whole pages
+/// Anything else is a bug in the caller, not corruption
+pub(crate) fn check_page_aligned(pos: u64, len: usize, page_size: usize) -> Result<()> {
+    if !pos.is_multiple_of(page_size as u64) || !len.is_multiple_of(page_size) {
+        return Err(TursoMiniError::Misuse(format!(
+            "IO must cover whole pages: pos={pos}, len={len}, page_size={page_size}"
+        )));
+    }
+    Ok(())
+}
+
+/// Holds a file lock and releases it on drop, including while unwinding from a panic
+pub struct FileLockGuard<'a> {
+    file: &'a dyn File,
+}
+
+impl<'a> FileLockGuard<'a> {
+    pub fn new(file: &'a dyn File) -> Result<Self> {
+        file.lock_file()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for FileLockGuard<'_> {
+    fn drop(&mut self) {
+        // drop can't return the error, the lock is released on close anyway
+        let _ = self.file.unlock_file();
+    }
 }
 
 pub trait IO: Clock + Send + Sync {
@@ -55,6 +109,7 @@ pub type ReadComplete = dyn Fn(Result<(Arc<Buffer>, i32), CompletionError>);
 pub type WriteComplete = dyn Fn(Result<i32, CompletionError>);
 pub type SyncComplete = dyn Fn(Result<i32, CompletionError>);
 pub type TruncateComplete = dyn Fn(Result<i32, CompletionError>);
+pub type DoneComplete = dyn Fn(Result<i32, CompletionError>);
 
 pub struct ReadCompletion {
     pub buf: Arc<Buffer>,
@@ -135,12 +190,25 @@ impl Debug for CompletionType {
      }
 }
 
-#[derive(Debug)]
 struct CompletionInner {
     completion_type: CompletionType,
-    result: OnceLock<Option<CompletionError>>,
+    result: OnceLock<Result<i32, CompletionError>>,
+    // extra callbacks attached with on_done, run after the typed callback
+    on_done: RefCell<Vec<Box<DoneComplete>>>,
 }
 
+impl Debug for CompletionInner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompletionInner")
+            .field("completion_type", &self.completion_type)
+            .field("result", &self.result)
+            .field("on_done", &self.on_done.borrow().len())
+            .finish()
+    }
+}
+
+// Clones are handles to the same completion
+#[derive(Clone)]
 pub struct Completion {
     inner: Arc<CompletionInner>,
 }
@@ -151,6 +219,7 @@ impl Completion {
             inner: Arc::new(CompletionInner { 
                 completion_type: completion_type, 
                 result: OnceLock::new(), 
+                on_done: RefCell::new(Vec::new()),
             }),
         }
     }
@@ -203,6 +272,19 @@ impl Completion {
         )))
     }
 
+    // Fire and forget variants for callers that don't care about the result
+    pub fn noop_write() -> Self {
+        Self::new_write(|_| {})
+    }
+
+    pub fn noop_sync() -> Self {
+        Self::new_sync(|_| {})
+    }
+
+    pub fn noop_trunc() -> Self {
+        Self::new_trunc(|_| {})
+    }
+
     pub fn complete(&self, result: i32) {
         let result = Ok(result);
         match &self.inner.completion_type {
@@ -211,7 +293,8 @@ impl Completion {
             CompletionType::Sync(s) => s.callback(result),
             CompletionType::Truncate(t) => t.callback(result),
         }
-        self.inner.result.set(None).expect("result must be set only once");
+        self.inner.result.set(result).expect("result must be set only once");
+        self.run_on_done(result);
     }
 
     pub fn error(&self, err: CompletionError) {
@@ -222,7 +305,34 @@ impl Completion {
             CompletionType::Sync(s) => s.callback(result),
             CompletionType::Truncate(t) => t.callback(result),
         }
-        self.inner.result.set(Some(err)).expect("result must be set only once");
+        self.inner.result.set(result).expect("result must be set only once");
+        self.run_on_done(result);
+    }
+
+    /// Attach another callback, run after the one given at construction
+    /// If the completion has already finished, f runs immediately with its result
+    /// Every callback runs exactly once
+    pub fn on_done<F>(&self, f: F)
+    where
+        F: Fn(Result<i32, CompletionError>) + 'static,
+    {
+        match self.inner.result.get() {
+            Some(&result) => f(result),
+            None => self.inner.on_done.borrow_mut().push(Box::new(f)),
+        }
+    }
+
+    fn run_on_done(&self, result: Result<i32, CompletionError>) {
+        // take the callbacks out so a callback calling on_done doesn't hit a RefCell borrow
+        let callbacks = std::mem::take(&mut *self.inner.on_done.borrow_mut());
+        for callback in callbacks {
+            callback(result);
+        }
+    }
+
+    /// Whether complete or error has been called on this completion
+    pub fn is_completed(&self) -> bool {
+        self.inner.result.get().is_some()
     }
 
     // Q. unreachable vs panic?
@@ -234,11 +344,119 @@ impl Completion {
             _ => unreachable!("this function must be called on ReadCompletion only")
         }
     }
+
+    // Non panicking accessors, for handlers that accept any completion type
+    pub fn try_as_read(&self) -> Option<&ReadCompletion> {
+        match self.inner.completion_type {
+            CompletionType::Read(ref r) => Some(r),
+            _ => None,
+        }
+    }
+
+    pub fn try_as_write(&self) -> Option<&WriteCompletion> {
+        match self.inner.completion_type {
+            CompletionType::Write(ref w) => Some(w),
+            _ => None,
+        }
+    }
+
+    pub fn try_as_sync(&self) -> Option<&SyncCompletion> {
+        match self.inner.completion_type {
+            CompletionType::Sync(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn try_as_trunc(&self) -> Option<&TruncateCompletion> {
+        match self.inner.completion_type {
+            CompletionType::Truncate(ref t) => Some(t),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::OpenFlags;
+    use std::{cell::Cell, panic, rc::Rc, sync::{atomic::{AtomicBool, Ordering}, Arc}};
+
+    use super::{Buffer, Completion, File, FileLockGuard, OpenFlags, Result};
+    use crate::io::error::CompletionError;
+
+    /// File that only tracks whether it is locked
+    #[derive(Default)]
+    struct LockTrackingFile {
+        locked: AtomicBool,
+    }
+
+    impl File for LockTrackingFile {
+        fn lock_file(&self) -> Result<()> {
+            self.locked.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn unlock_file(&self) -> Result<()> {
+            self.locked.store(false, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn pread(&self, _pos: u64, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn pwrite(&self, _pos: u64, _buffer: Arc<Buffer>, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn sync(&self, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn truncate(&self, _len: u64, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn size(&self) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn pwritev(&self, _pos: u64, _buffers: Vec<Arc<Buffer>>, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+    }
+
+    #[test]
+    fn test_lock_scoped_unlocks_on_drop() {
+        let file = LockTrackingFile::default();
+        {
+            let _guard = file.lock_scoped().unwrap();
+            assert!(file.locked.load(Ordering::SeqCst));
+        }
+        assert!(!file.locked.load(Ordering::SeqCst));
+
+        // trait objects go through FileLockGuard::new
+        let dyn_file: &dyn File = &file;
+        let guard = FileLockGuard::new(dyn_file).unwrap();
+        assert!(file.locked.load(Ordering::SeqCst));
+        drop(guard);
+        assert!(!file.locked.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_lock_scoped_unlocks_on_panic() {
+        let file = LockTrackingFile::default();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = file.lock_scoped().unwrap();
+            assert!(file.locked.load(Ordering::SeqCst));
+            panic!("failure while holding the lock");
+        }));
+        assert!(result.is_err());
+        assert!(!file.locked.load(Ordering::SeqCst));
+    }
 
     #[test]
     fn test_individual_flags() {
@@ -266,4 +484,75 @@ mod tests {
         let default_flags = OpenFlags::default();
         assert_eq!(default_flags, OpenFlags::Create);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_on_done_runs_alongside_original() {
+        let original = Rc::new(Cell::new(0));
+        let chained = Rc::new(Cell::new(0));
+        let c = {
+            let original = original.clone();
+            Completion::new_write(move |res| {
+                assert_eq!(res.unwrap(), 512);
+                original.set(original.get() + 1);
+            })
+        };
+        {
+            let chained = chained.clone();
+            c.on_done(move |res| {
+                assert_eq!(res.unwrap(), 512);
+                chained.set(chained.get() + 1);
+            });
+        }
+
+        c.complete(512);
+        assert_eq!(original.get(), 1);
+        assert_eq!(chained.get(), 1);
+    }
+
+    #[test]
+    fn test_on_done_after_completion_runs_immediately() {
+        let c = Completion::new_read(Arc::new(Buffer::new_zeroed(4)), |_| {});
+        c.error(CompletionError::IOError(std::io::ErrorKind::UnexpectedEof));
+
+        let seen = Rc::new(Cell::new(None));
+        {
+            let seen = seen.clone();
+            c.on_done(move |res| seen.set(Some(res.is_err())));
+        }
+        assert_eq!(seen.get(), Some(true));
+    }
+
+    #[test]
+    fn test_noop_completions() {
+        let c = Completion::noop_write();
+        assert!(!c.is_completed());
+        c.complete(4096);
+        assert!(c.is_completed());
+
+        let c = Completion::noop_sync();
+        c.complete(0);
+        assert!(c.is_completed());
+
+        let c = Completion::noop_trunc();
+        c.error(CompletionError::IOError(std::io::ErrorKind::Other));
+        assert!(c.is_completed());
+    }
+
+    #[test]
+    fn test_try_as_accessors() {
+        let read = Completion::new_read(Arc::new(Buffer::new_zeroed(8)), |_| {});
+        assert_eq!(read.try_as_read().unwrap().buf().len(), 8);
+        assert!(read.try_as_write().is_none());
+        assert!(read.try_as_sync().is_none());
+        assert!(read.try_as_trunc().is_none());
+
+        let write = Completion::noop_write();
+        assert!(write.try_as_write().is_some());
+        assert!(write.try_as_read().is_none());
+
+        assert!(Completion::noop_sync().try_as_sync().is_some());
+        assert!(Completion::noop_sync().try_as_trunc().is_none());
+        assert!(Completion::noop_trunc().try_as_trunc().is_some());
+        assert!(Completion::noop_trunc().try_as_read().is_none());
+    }
+}
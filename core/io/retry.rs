@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use crate::{error::TursoMiniError, io::File, Result};
+
+/// Decides whether an operation that failed with Busy should be retried
+/// e.g. lock_file while another connection holds the lock
+pub trait RetryPolicy: Send + Sync {
+    /// Delay before retry number `attempt` (starting at 0), or None to give up
+    fn backoff(&self, attempt: u32) -> Option<Duration>;
+}
+
+/// Doubles the delay after every attempt, capped at max_delay
+pub struct ExponentialBackoff {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        Some(self.initial_delay.saturating_mul(factor).min(self.max_delay))
+    }
+}
+
+/// Runs op, retrying while it returns Busy and the policy allows it
+/// Any other error is returned immediately
+pub fn retry_on_busy<T, F>(policy: &dyn RetryPolicy, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(TursoMiniError::Busy) => match policy.backoff(attempt) {
+                Some(delay) => {
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                None => return Err(TursoMiniError::Busy),
+            },
+            res => return res,
+        }
+    }
+}
+
+/// Takes the file lock, asking policy whether to try again each time
+/// another holder makes lock_file return Busy
+/// There is no pager yet, once there is it should lock through this
+/// so Busy only reaches its caller after the policy gives up
+pub fn lock_with_retry(file: &dyn File, policy: &dyn RetryPolicy) -> Result<()> {
+    retry_on_busy(policy, || file.lock_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc, Barrier,
+        },
+        time::Duration,
+    };
+
+    use super::{lock_with_retry, retry_on_busy, ExponentialBackoff, RetryPolicy};
+    use crate::error::TursoMiniError;
+    use crate::io::{memory::MemoryFile, File};
+
+    fn no_delay(max_retries: u32) -> ExponentialBackoff {
+        ExponentialBackoff {
+            max_retries,
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_exponential_backoff_delays() {
+        let policy = ExponentialBackoff {
+            max_retries: 4,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+        };
+        assert_eq!(policy.backoff(0), Some(Duration::from_millis(10)));
+        assert_eq!(policy.backoff(1), Some(Duration::from_millis(20)));
+        assert_eq!(policy.backoff(2), Some(Duration::from_millis(40)));
+        assert_eq!(policy.backoff(3), Some(Duration::from_millis(50)));
+        assert_eq!(policy.backoff(4), None);
+    }
+
+    /// Never waits, counts how often it is consulted and can release
+    /// the lock on the file before a given retry
+    struct ReleasingPolicy<'a> {
+        max_retries: u32,
+        release: Option<(&'a MemoryFile, u32)>,
+        consulted: AtomicU32,
+    }
+
+    impl RetryPolicy for ReleasingPolicy<'_> {
+        fn backoff(&self, attempt: u32) -> Option<Duration> {
+            self.consulted.fetch_add(1, Ordering::Relaxed);
+            if attempt >= self.max_retries {
+                return None;
+            }
+            if let Some((file, before)) = self.release {
+                if attempt + 1 == before {
+                    file.unlock_file().unwrap();
+                }
+            }
+            Some(Duration::ZERO)
+        }
+    }
+
+    #[test]
+    fn test_memory_file_lock_is_busy_while_held() {
+        let file = MemoryFile::new("locked.db");
+        file.lock_file().unwrap();
+        assert!(matches!(file.lock_file(), Err(TursoMiniError::Busy)));
+        file.unlock_file().unwrap();
+        file.lock_file().unwrap();
+    }
+
+    #[test]
+    fn test_memory_file_unlock_without_lock_is_misuse() {
+        let file = MemoryFile::new("locked.db");
+        assert!(matches!(file.unlock_file(), Err(TursoMiniError::Misuse(_))));
+        file.lock_file().unwrap();
+        file.unlock_file().unwrap();
+        assert!(matches!(file.unlock_file(), Err(TursoMiniError::Misuse(_))));
+    }
+
+    #[test]
+    fn test_memory_file_lock_race_has_one_winner() {
+        for _ in 0..100 {
+            let file = Arc::new(MemoryFile::new("raced.db"));
+            let barrier = Arc::new(Barrier::new(2));
+            let handles: Vec<_> = (0..2)
+                .map(|_| {
+                    let (file, barrier) = (file.clone(), barrier.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        file.lock_file().is_ok()
+                    })
+                })
+                .collect();
+            let winners = handles.into_iter().map(|h| h.join().unwrap()).filter(|&won| won).count();
+            // loser must have seen Busy rather than also taking the lock
+            assert_eq!(winners, 1);
+        }
+    }
+
+    #[test]
+    fn test_retry_gives_up_while_lock_held() {
+        let file = MemoryFile::new("locked.db");
+        file.lock_file().unwrap();
+
+        let policy = ReleasingPolicy {
+            max_retries: 3,
+            release: None,
+            consulted: AtomicU32::new(0),
+        };
+        assert!(matches!(lock_with_retry(&file, &policy), Err(TursoMiniError::Busy)));
+        // first attempt plus 3 retries, each one Busy
+        assert_eq!(policy.consulted.load(Ordering::Relaxed), 4);
+    }
+
+    #[test]
+    fn test_retry_succeeds_once_lock_released() {
+        let file = MemoryFile::new("locked.db");
+        file.lock_file().unwrap();
+
+        // the holder releases the lock before the second retry
+        let policy = ReleasingPolicy {
+            max_retries: 5,
+            release: Some((&file, 2)),
+            consulted: AtomicU32::new(0),
+        };
+        lock_with_retry(&file, &policy).unwrap();
+        // Busy on the first attempt and the first retry, the second retry succeeds
+        assert_eq!(policy.consulted.load(Ordering::Relaxed), 2);
+        // and the lock is now held by the retrying caller
+        assert!(matches!(file.lock_file(), Err(TursoMiniError::Busy)));
+    }
+
+    #[test]
+    fn test_retry_does_not_retry_other_errors() {
+        let attempts = Cell::new(0);
+        let res: Result<(), _> = retry_on_busy(&no_delay(5), || {
+            attempts.set(attempts.get() + 1);
+            Err(TursoMiniError::Corrupt("bad".to_string()))
+        });
+        assert!(matches!(res, Err(TursoMiniError::Corrupt(_))));
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::storage::sqlite3_ondisk::PageType;
+
 /// B-Tree Page Layout:
 /// 
 /// ┌─────────────────┬─────────────────┬─────────────────┬─────────────────┐
@@ -26,4 +28,78 @@ pub mod offset {
     pub const BTREE_CELL_CONTENT_AREA: usize = 5;
     pub const BTREE_FRAGMENTED_BYTES_COUNT: usize = 7;
     pub const BTREE_RIGHTMOST_PTR: usize = 8;
-}
\ No newline at end of file
+}
+
+/// How a table's rows are stored
+/// Rowid tables use a table b-tree keyed by rowid
+/// WITHOUT ROWID tables use an index b-tree keyed by the primary key,
+/// where each index cell payload is the whole row record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableKind {
+    Rowid,
+    WithoutRowid,
+}
+
+impl TableKind {
+    /// Derive the kind of a table from the type of its root page
+    pub fn from_root_page_type(page_type: &PageType) -> Self {
+        if page_type.is_table() {
+            Self::Rowid
+        } else {
+            Self::WithoutRowid
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::TableKind;
+    use crate::{
+        io::Buffer,
+        storage::sqlite3_ondisk::{write_varint, PageContent, PageType, LEAF_PAGE_HEADER_SIZE_BYTES},
+        types::{read_record, RecordBuilder, Text, Value},
+    };
+
+    #[test]
+    fn test_table_kind_from_root_page_type() {
+        assert_eq!(TableKind::from_root_page_type(&PageType::TableLeaf), TableKind::Rowid);
+        assert_eq!(TableKind::from_root_page_type(&PageType::TableInterior), TableKind::Rowid);
+        assert_eq!(TableKind::from_root_page_type(&PageType::IndexLeaf), TableKind::WithoutRowid);
+        assert_eq!(TableKind::from_root_page_type(&PageType::IndexInterior), TableKind::WithoutRowid);
+    }
+
+    #[test]
+    fn test_table_kind_of_without_rowid_root_page() {
+        // root page of CREATE TABLE t(k TEXT PRIMARY KEY, v INT) WITHOUT ROWID:
+        // an index leaf whose cells are payload size (varint) | row record
+        let rows = [
+            RecordBuilder::new().push_text("a").push_int(1).build(),
+            RecordBuilder::new().push_text("b").push_int(2).build(),
+        ];
+        let page = PageContent::new(0, Arc::new(Buffer::new_zeroed(512)));
+        page.write_page_type(PageType::IndexLeaf as u8);
+        let mut content_start = 512;
+        for (i, record) in rows.iter().enumerate() {
+            let mut cell = vec![0u8; 9];
+            let n = write_varint(&mut cell, record.len() as u64);
+            cell.truncate(n);
+            cell.extend_from_slice(record);
+            content_start -= cell.len();
+            page.as_ptr()[content_start..content_start + cell.len()].copy_from_slice(&cell);
+            page.write_u16_no_offset(LEAF_PAGE_HEADER_SIZE_BYTES + i * 2, content_start as u16);
+        }
+        page.write_cell_count(rows.len() as u16);
+        page.write_cell_content_area(content_start as u16);
+
+        let kind = TableKind::from_root_page_type(&page.try_page_type().unwrap());
+        assert_eq!(kind, TableKind::WithoutRowid);
+        // each index cell payload is the whole row, primary key first
+        let payload = page.cell_payload(1, 512).unwrap();
+        assert_eq!(
+            read_record(payload.local).unwrap(),
+            vec![Value::Text(Text::new("b")), Value::Integer(2)]
+        );
+    }
+}
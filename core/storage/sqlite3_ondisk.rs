@@ -1,12 +1,19 @@
-use std::{pin::Pin, sync::Arc};
+use std::{cell::Cell, collections::{BTreeMap, HashSet}, pin::Pin, rc::Rc, sync::Arc};
 
-use crate::{bail_corrupt_error, error::TursoMiniError, io::Buffer, storage::btree::offset::{BTREE_CELL_CONTENT_AREA, BTREE_CELL_COUNT, BTREE_FIRST_FREEBLOCK, BTREE_FRAGMENTED_BYTES_COUNT, BTREE_PAGE_TYPE, BTREE_RIGHTMOST_PTR}, Result};
-use pack1::{U16BE};
+use crate::{bail_corrupt_error, error::{CompletionError, TursoMiniError}, io::{Buffer, Completion, File, OpenFlags, IO}, storage::btree::offset::{BTREE_CELL_CONTENT_AREA, BTREE_CELL_COUNT, BTREE_FIRST_FREEBLOCK, BTREE_FRAGMENTED_BYTES_COUNT, BTREE_PAGE_TYPE, BTREE_RIGHTMOST_PTR}, Result};
+use pack1::{I32BE, U16BE, U32BE};
 
 pub const CELL_PTR_SIZE_BYTES: usize = 2;
 pub const INTERIOR_PAGE_HEADER_SIZE_BYTES: usize = 12;
 pub const LEAF_PAGE_HEADER_SIZE_BYTES: usize = 8;
+pub const DATABASE_HEADER_SIZE: usize = 100;
 
+// Every SQLite database file starts with these 16 bytes
+pub const SQLITE_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+// repr(transparent) so PageSize has the layout of U16BE inside DatabaseHeader
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
 pub struct PageSize(U16BE);
 
 impl PageSize {
@@ -18,12 +25,7 @@ impl PageSize {
     // This fn has no heap allocation, no side effects, simple bit operations
     // And hence is a suitable candidate for const fn
     pub const fn new(size: u32) -> Option<Self> {
-        if size < PageSize::MIN || size > PageSize::MAX {
-            return None;
-        }
-
-        // Page size must be power of 2
-        if size.count_ones() != 1 {
+        if !Self::is_valid(size) {
             return None;
         }
 
@@ -36,6 +38,16 @@ impl PageSize {
         Some(Self(U16BE::new(size as u16)))
     }
 
+    /// A page size must be a power of 2 between 512 and 65536
+    pub const fn is_valid(size: u32) -> bool {
+        size >= PageSize::MIN && size <= PageSize::MAX && size.count_ones() == 1
+    }
+
+    /// Every valid page size in increasing order: 512, 1024, ..., 65536
+    pub fn all_valid() -> impl Iterator<Item = u32> {
+        (PageSize::MIN.trailing_zeros()..=PageSize::MAX.trailing_zeros()).map(|shift| 1 << shift)
+    }
+
     pub fn new_from_header_u16(value: u16) -> Result<Self> {
         match value {
             1 => Ok(Self(U16BE::new(1))),
@@ -66,6 +78,237 @@ impl Default for PageSize {
     }
 }
 
+/// Text encoding of all strings in the database, stored at header offset 56
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8 = 1,
+    Utf16Le = 2,
+    Utf16Be = 3,
+}
+
+/// Journaling mode, from the file format version bytes at header offsets 18 and 19
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalMode {
+    Rollback,
+    Wal,
+}
+
+/// Auto-vacuum mode, derived from header offsets 52 and 64
+/// Any mode other than None means the file contains pointer map pages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoVacuumMode {
+    None,
+    Full,
+    Incremental,
+}
+
+/* Database header: the first 100 bytes of page 1
+Documentation: https://www.sqlite.org/fileformat.html#the_database_header
+
+All multi byte fields are big endian. Every field type has an alignment of 1,
+so the struct has exactly the on-disk layout and can be copied to and from bytes
+*/
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct DatabaseHeader {
+    pub magic: [u8; 16],                         // 0
+    pub page_size: PageSize,                     // 16
+    pub write_version: u8,                       // 18: 1 = rollback journal, 2 = WAL
+    pub read_version: u8,                        // 19: 1 = rollback journal, 2 = WAL
+    pub reserved_space: u8,                      // 20: unused bytes at the end of each page
+    pub max_embed_frac: u8,                      // 21: must be 64
+    pub min_embed_frac: u8,                      // 22: must be 32
+    pub min_leaf_frac: u8,                       // 23: must be 32
+    pub change_counter: U32BE,                   // 24
+    pub database_size: U32BE,                    // 28: in pages
+    pub freelist_trunk_page: U32BE,              // 32
+    pub freelist_pages: U32BE,                   // 36
+    pub schema_cookie: U32BE,                    // 40
+    pub schema_format: U32BE,                    // 44
+    pub default_page_cache_size: I32BE,          // 48
+    pub vacuum_mode_largest_root_page: U32BE,    // 52
+    pub text_encoding: U32BE,                    // 56
+    pub user_version: I32BE,                     // 60
+    pub incremental_vacuum_enabled: U32BE,       // 64
+    pub application_id: I32BE,                   // 68
+    pub reserved_for_expansion: [u8; 20],        // 72: must be zero
+    pub version_valid_for: U32BE,                // 92
+    pub version_number: U32BE,                   // 96
+}
+
+const _: () = assert!(std::mem::size_of::<DatabaseHeader>() == DATABASE_HEADER_SIZE);
+const _: () = assert!(std::mem::align_of::<DatabaseHeader>() == 1);
+
+impl DatabaseHeader {
+    /// Parses the header from the start of page 1
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < DATABASE_HEADER_SIZE {
+            bail_corrupt_error!("database header too short: {} bytes", buf.len());
+        }
+        // SAFETY: DatabaseHeader is repr(C), made only of byte arrays, pack1 types
+        // that are byte arrays and PageSize, which is repr(transparent) over U16BE.
+        // So it has no padding, an alignment of 1 and every bit pattern is valid.
+        // The length was checked above
+        let header = unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const DatabaseHeader) };
+
+        if &header.magic != SQLITE_MAGIC {
+            bail_corrupt_error!("invalid magic string in database header");
+        }
+        PageSize::new_from_header_u16(header.page_size.get_raw())?;
+        if header.text_encoding.get() > TextEncoding::Utf16Be as u32 {
+            bail_corrupt_error!("invalid text encoding in database header: {}", header.text_encoding.get());
+        }
+        Ok(header)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; DATABASE_HEADER_SIZE] {
+        // SAFETY: see from_bytes, the struct is exactly DATABASE_HEADER_SIZE plain bytes
+        unsafe { &*(self as *const DatabaseHeader as *const [u8; DATABASE_HEADER_SIZE]) }
+    }
+
+    /// Writes the header to the start of page 1
+    pub fn write_to(&self, buf: &mut [u8]) {
+        buf[..DATABASE_HEADER_SIZE].copy_from_slice(self.as_bytes());
+    }
+
+    /// SQLITE_VERSION_NUMBER of the library that most recently modified the file
+    /// e.g. 3046000 for 3.46.0
+    pub fn sqlite_version(&self) -> u32 {
+        self.version_number.get()
+    }
+
+    /// Value of the change counter when sqlite_version was stored
+    pub fn version_valid_for(&self) -> u32 {
+        self.version_valid_for.get()
+    }
+
+    /// First freelist trunk page, 0 if the freelist is empty
+    pub fn freelist_head_page(&self) -> u32 {
+        self.freelist_trunk_page.get()
+    }
+
+    pub fn set_freelist_head_page(&mut self, page_no: u32) {
+        self.freelist_trunk_page = U32BE::new(page_no);
+    }
+
+    /// Total number of free pages, trunks included
+    pub fn freelist_page_count(&self) -> u32 {
+        self.freelist_pages.get()
+    }
+
+    pub fn set_freelist_page_count(&mut self, count: u32) {
+        self.freelist_pages = U32BE::new(count);
+    }
+
+    /// Schema version, changes whenever the schema does so cached schemas can be invalidated
+    pub fn schema_cookie(&self) -> u32 {
+        self.schema_cookie.get()
+    }
+
+    pub fn set_schema_cookie(&mut self, value: u32) {
+        self.schema_cookie = U32BE::new(value);
+    }
+
+    /// To be called by every schema change
+    pub fn bump_schema_cookie(&mut self) {
+        self.set_schema_cookie(self.schema_cookie().wrapping_add(1));
+    }
+
+    /// PRAGMA user_version, free for the application to use
+    pub fn user_version(&self) -> i32 {
+        self.user_version.get()
+    }
+
+    pub fn set_user_version(&mut self, value: i32) {
+        self.user_version = I32BE::new(value);
+    }
+
+    /// PRAGMA application_id, identifies the application file format
+    pub fn application_id(&self) -> i32 {
+        self.application_id.get()
+    }
+
+    pub fn set_application_id(&mut self, value: i32) {
+        self.application_id = I32BE::new(value);
+    }
+
+    /// Both version bytes are 2 in WAL mode and 1 with a rollback journal.
+    /// A file is only treated as WAL if both say so, like SQLite
+    pub fn journal_mode(&self) -> JournalMode {
+        if self.write_version == 2 && self.read_version == 2 {
+            JournalMode::Wal
+        } else {
+            JournalMode::Rollback
+        }
+    }
+
+    /// A non zero largest root b-tree page turns auto-vacuum on, the
+    /// incremental vacuum flag then selects between full and incremental mode
+    pub fn auto_vacuum(&self) -> AutoVacuumMode {
+        if self.vacuum_mode_largest_root_page.get() == 0 {
+            AutoVacuumMode::None
+        } else if self.incremental_vacuum_enabled.get() != 0 {
+            AutoVacuumMode::Incremental
+        } else {
+            AutoVacuumMode::Full
+        }
+    }
+
+    /// Size of the database in pages. The in-header size is only trusted when it is
+    /// non zero and was written by a version that also bumped the change counter
+    /// (version_valid_for == change_counter), otherwise it is derived from the file size,
+    /// counting a trailing partial page as a page like SQLite does
+    pub fn page_count(&self, file_size: u64) -> u32 {
+        let database_size = self.database_size.get();
+        if database_size != 0 && self.version_valid_for.get() == self.change_counter.get() {
+            return database_size;
+        }
+        let page_size = self.page_size.get() as u64;
+        file_size.div_ceil(page_size) as u32
+    }
+
+    /// A header value of 0 means the encoding was never set, SQLite treats it as UTF-8
+    pub fn text_encoding(&self) -> TextEncoding {
+        match self.text_encoding.get() {
+            2 => TextEncoding::Utf16Le,
+            3 => TextEncoding::Utf16Be,
+            _ => TextEncoding::Utf8,
+        }
+    }
+}
+
+// Header of a freshly created single page database
+impl Default for DatabaseHeader {
+    fn default() -> Self {
+        Self {
+            magic: *SQLITE_MAGIC,
+            page_size: PageSize::default(),
+            write_version: 1,
+            read_version: 1,
+            reserved_space: 0,
+            max_embed_frac: 64,
+            min_embed_frac: 32,
+            min_leaf_frac: 32,
+            change_counter: U32BE::new(1),
+            database_size: U32BE::new(1),
+            freelist_trunk_page: U32BE::new(0),
+            freelist_pages: U32BE::new(0),
+            schema_cookie: U32BE::new(0),
+            schema_format: U32BE::new(4),
+            default_page_cache_size: I32BE::new(0),
+            vacuum_mode_largest_root_page: U32BE::new(0),
+            text_encoding: U32BE::new(TextEncoding::Utf8 as u32),
+            user_version: I32BE::new(0),
+            incremental_vacuum_enabled: U32BE::new(0),
+            application_id: I32BE::new(0),
+            reserved_for_expansion: [0; 20],
+            version_valid_for: U32BE::new(1),
+            version_number: U32BE::new(3046000),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageType {
     IndexInterior = 2,
     TableInterior = 5,
@@ -96,6 +339,23 @@ impl TryFrom<u8> for PageType {
     }
 }
 
+/// Payload of a cell as stored on the page
+/// Only the first local.len() bytes live on the page, the remaining
+/// overflow_len() bytes are in the overflow chain starting at first_overflow_page
+#[derive(Debug)]
+pub struct CellPayload<'a> {
+    pub payload_len: usize,
+    pub local: &'a [u8],
+    pub first_overflow_page: Option<u32>,
+}
+
+impl CellPayload<'_> {
+    pub fn overflow_len(&self) -> usize {
+        self.payload_len - self.local.len()
+    }
+}
+
+#[derive(Clone)]
 pub struct OverflowCell {
     pub index: usize,
     pub payload: Pin<Vec<u8>>,
@@ -121,13 +381,45 @@ impl PageContent {
         }
     }
 
+    /// Copies the page bytes into a new buffer so the copy can be mutated
+    /// without affecting the original (copy-on-write snapshots)
+    pub fn deep_copy(&self) -> PageContent {
+        Self {
+            offset: self.offset,
+            buffer: Arc::new(Buffer::new(self.buffer.as_slice().to_vec())),
+            overflow_cells: self.overflow_cells.clone(),
+        }
+    }
+
+    /// Returns the overflow cell staged for the given cell index, if any
+    pub fn overflow_cell_for(&self, index: usize) -> Option<&OverflowCell> {
+        self.overflow_cells.iter().find(|cell| cell.index == index)
+    }
+
+    /// Drops all staged overflow cells, e.g. once a balance has placed them
+    pub fn clear_overflow_cells(&mut self) {
+        self.overflow_cells.clear();
+    }
+
+    /// # Panics
+    /// If the buffer is too short to hold the type byte or the byte is not a
+    /// valid page type. Use try_page_type for pages read from disk
     pub fn page_type(&self) -> PageType {
         // PageType is present just after header
-        self.read_u8(BTREE_PAGE_TYPE).try_into().unwrap()
+        self.try_page_type().unwrap()
+    }
+
+    /// Like page_type but returns a Corrupt error instead of panicking
+    /// when the buffer is too short or the type byte is invalid
+    pub fn try_page_type(&self) -> Result<PageType> {
+        let Some(value) = self.try_read_u8(BTREE_PAGE_TYPE) else {
+            bail_corrupt_error!("page buffer too short to read page type: len={}", self.buffer.len())
+        };
+        value.try_into()
     }
 
     pub fn maybe_page_type(&self) -> Option<PageType> {
-        self.read_u8(0).try_into().ok()
+        self.try_read_u8(BTREE_PAGE_TYPE)?.try_into().ok()
     }
 
     // Reads any byte at pos bytes after page header
@@ -136,6 +428,16 @@ impl PageContent {
         buf[self.offset + pos]
     }
 
+    // Same as read_u8 but returns None if pos is past the end of the buffer
+    pub fn try_read_u8(&self, pos: usize) -> Option<u8> {
+        self.buffer.as_slice().get(self.offset + pos).copied()
+    }
+
+    /// Hex dump of the whole page buffer, including the database header on page 1
+    pub fn hexdump(&self) -> String {
+        self.buffer.hexdump()
+    }
+
     pub fn as_ptr(&self) -> &mut [u8] {
         self.buffer.as_mut_slice()
     }
@@ -146,11 +448,6 @@ impl PageContent {
         u16::from_be_bytes([buf[self.offset + pos],buf[self.offset + pos + 1]])
     }
 
-    fn read_u32(&self, pos: usize) -> u32 {
-        let buf = self.as_ptr();
-        read_u32(buf, self.offset + pos)
-    }
-
     fn write_u8(&self, pos: usize, value: u8) {
         let buf = self.as_ptr();
         buf[self.offset + pos] = value;
@@ -211,15 +508,17 @@ impl PageContent {
     */
     pub fn write_freeblock(&self, offset: u16, next_block: Option<u16>, size: u16) {
         self.write_freeblock_next_ptr(offset, next_block.unwrap_or(0));
-        self.write_freeblock_size(offset + 2, size);
+        self.write_freeblock_size(offset, size);
     }
 
+    // Freeblock offsets are from the start of the page, like cell pointers,
+    // so they are not shifted by the page 1 header offset
     fn write_freeblock_next_ptr(&self, offset: u16, next_block: u16) {
-        self.write_u16(offset as usize, next_block);
+        self.write_u16_no_offset(offset as usize, next_block);
     }
 
     fn write_freeblock_size(&self, offset: u16, size: u16) {
-        self.write_u16(offset as usize + 2, size);
+        self.write_u16_no_offset(offset as usize + 2, size);
     }
 
     pub fn read_freeblock(&self, offset: u16) -> (u16, u16) {
@@ -229,6 +528,92 @@ impl PageContent {
         )
     }
 
+    /// Walks the freeblock chain and checks it is well formed:
+    /// blocks lie inside the cell content area and the usable part of the page,
+    /// are at least 4 bytes (room for the next pointer and size) and are in
+    /// strictly increasing order, which also rules out cycles.
+    /// Like SQLite's btreeComputeFreeSpace, a block must start more than 3 bytes
+    /// after the previous one ends: closer blocks should have been coalesced
+    /// since only gaps of up to 3 bytes may be left as fragments
+    pub fn validate_freeblocks(&self, usable_size: usize) -> Result<()> {
+        let content_area = self.cell_content_area() as usize;
+        let mut prev_end = None;
+        let mut pc = self.read_first_freeblock() as usize;
+        while pc != 0 {
+            if let Some(prev_end) = prev_end {
+                if pc <= prev_end + 3 {
+                    bail_corrupt_error!(
+                        "freeblock at {pc} is out of order, overlaps or is adjacent to the previous one ending at {prev_end}"
+                    );
+                }
+            }
+            if pc < content_area {
+                bail_corrupt_error!("freeblock at {pc} starts before the cell content area at {content_area}");
+            }
+            if pc + 4 > usable_size {
+                bail_corrupt_error!("freeblock at {pc} extends past the usable size {usable_size}");
+            }
+            let (next, size) = self.read_freeblock(pc as u16);
+            let size = size as usize;
+            if size < 4 {
+                bail_corrupt_error!("freeblock at {pc} has size {size}, less than 4");
+            }
+            if pc + size > usable_size {
+                bail_corrupt_error!("freeblock at {pc} of size {size} extends past the usable size {usable_size}");
+            }
+            prev_end = Some(pc + size);
+            pc = next as usize;
+        }
+        Ok(())
+    }
+
+    /// Share of the free space on the page that is not part of the contiguous
+    /// unallocated region, i.e. fragments plus freeblocks over all free bytes.
+    /// 0.0 means all free space is contiguous, values near 1.0 mean a
+    /// defragmentation would recover most of it. The freeblock walk stops at
+    /// the first block outside usable_size or out of order, see validate_freeblocks
+    pub fn fragmentation_ratio(&self, usable_size: usize) -> f64 {
+        let scattered = self.freeblock_bytes(usable_size) + self.num_frag_free_bytes() as usize;
+        // not unallocated_region_size, which underflows when a corrupt header puts
+        // the content area before the end of the cell pointer array
+        let unallocated = (self.cell_content_area() as usize).saturating_sub(self.unallocated_region_start());
+        let total = scattered + unallocated;
+        if total == 0 {
+            return 0.0;
+        }
+        scattered as f64 / total as f64
+    }
+
+    /// Whether a cell of cell_size bytes plus its pointer fits in the free space
+    /// of the page, counting freeblocks and fragments a defragmentation would merge.
+    /// Nothing fits on a page whose content area starts inside the cell pointer array
+    pub fn can_fit_cell(&self, cell_size: usize, usable_size: usize) -> bool {
+        let Some(unallocated) =
+            (self.cell_content_area() as usize).checked_sub(self.unallocated_region_start())
+        else {
+            return false;
+        };
+        let free = unallocated
+            + self.freeblock_bytes(usable_size)
+            + self.num_frag_free_bytes() as usize;
+        cell_size + CELL_PTR_SIZE_BYTES <= free
+    }
+
+    // Sum of freeblock sizes, stops at the first block outside usable_size or out of order
+    fn freeblock_bytes(&self, usable_size: usize) -> usize {
+        let mut total = 0;
+        let mut pc = self.read_first_freeblock() as usize;
+        while pc != 0 && pc + 4 <= usable_size {
+            let (next, size) = self.read_freeblock(pc as u16);
+            total += size as usize;
+            if next as usize <= pc {
+                break;
+            }
+            pc = next as usize;
+        }
+        total
+    }
+
     pub fn write_cell_count(&self, count: u16) {
         self.write_u16(BTREE_CELL_COUNT, count);
     }
@@ -242,10 +627,31 @@ impl PageContent {
         self.write_u16(BTREE_CELL_CONTENT_AREA, value);
     }
  
+    /// Sets the start of the cell content area, encoding 65536 as 0
+    /// Fails with Misuse if value falls inside the header and cell pointer array
+    /// or past the end of this page, neither can be a valid offset
+    pub fn set_cell_content_area(&self, value: u32) -> Result<()> {
+        let page_len = self.buffer.len();
+        let array_end = self.cell_pointer_array_offset() + self.cell_pointer_array_size();
+        if (value as usize) < array_end || value as usize > page_len {
+            return Err(TursoMiniError::Misuse(format!(
+                "cell content area {value} out of range for page of {page_len} bytes with cell pointer array ending at {array_end}"
+            )));
+        }
+        self.write_cell_content_area(if value == PageSize::MAX { 0 } else { value as u16 });
+        Ok(())
+    }
+
     pub fn write_fragmented_bytes_count(&self, count: u8) {
         self.write_u8(BTREE_FRAGMENTED_BYTES_COUNT, count);
     }
 
+    /// True if the page holds no cells. An interior page with no cells has
+    /// no children besides the rightmost pointer, so the same check covers both
+    pub fn is_empty_page(&self) -> bool {
+        self.read_cell_count() == 0
+    }
+
     pub fn header_size(&self) -> usize {
         let is_interior = self.read_u8(BTREE_PAGE_TYPE) <= PageType::TableInterior as u8;
         (is_interior as usize) * INTERIOR_PAGE_HEADER_SIZE_BYTES
@@ -267,6 +673,151 @@ impl PageContent {
         self.read_cell_count() as usize * CELL_PTR_SIZE_BYTES
     }
 
+    /// Offset of the cell at idx from the start of the page, read from the cell pointer array
+    pub fn cell_pointer(&self, idx: usize) -> Result<usize> {
+        let count = self.read_cell_count() as usize;
+        if idx >= count {
+            bail_corrupt_error!("cell index {idx} out of range, page has {count} cells");
+        }
+        let ptr_pos = self.cell_pointer_array_offset() + idx * CELL_PTR_SIZE_BYTES;
+        match try_read_u16(self.buffer.as_slice(), ptr_pos) {
+            Some(ptr) => Ok(ptr as usize),
+            None => bail_corrupt_error!("cell pointer {idx} runs past the end of the page"),
+        }
+    }
+
+    /// Opens (delta > 0) or closes (delta < 0) |delta| slots in the cell pointer
+    /// array at index at, moving the pointers after it and updating the cell count.
+    /// Opened slots are left holding their old bytes, the caller fills them in.
+    /// Fails without touching the page: with Misuse if at or delta is out of range,
+    /// i.e. the grown array would run into the cell content area or the slots to
+    /// remove don't exist, and with Corrupt if the array already overlaps the content area
+    pub fn shift_cell_pointers(&self, at: usize, delta: isize) -> Result<()> {
+        let count = self.read_cell_count() as usize;
+        if at > count {
+            return Err(TursoMiniError::Misuse(format!(
+                "cell pointer index {at} out of range, page has {count} cells"
+            )));
+        }
+        let array_start = self.cell_pointer_array_offset();
+        let slot = |idx: usize| array_start + idx * CELL_PTR_SIZE_BYTES;
+        let array_end = slot(count);
+        let content_area = self.cell_content_area() as usize;
+        if content_area > self.buffer.len() {
+            bail_corrupt_error!(
+                "cell content area {content_area} is past the end of the page of {} bytes",
+                self.buffer.len()
+            );
+        }
+        if array_end > content_area {
+            bail_corrupt_error!(
+                "cell pointer array ending at {array_end} overlaps the cell content area at {content_area}"
+            );
+        }
+        let new_count = if delta >= 0 {
+            let added = delta as usize;
+            let new_count = count + added;
+            if new_count > u16::MAX as usize {
+                return Err(TursoMiniError::Misuse(format!(
+                    "cell count {new_count} does not fit in the page header"
+                )));
+            }
+            if slot(new_count) > content_area {
+                return Err(TursoMiniError::Misuse(format!(
+                    "cell pointer array ending at {} would overlap the cell content area at {content_area}",
+                    slot(new_count)
+                )));
+            }
+            self.as_ptr().copy_within(slot(at)..array_end, slot(at + added));
+            new_count
+        } else {
+            let removed = delta.unsigned_abs();
+            if at + removed > count {
+                return Err(TursoMiniError::Misuse(format!(
+                    "cannot remove {removed} cell pointers at {at}, page has {count} cells"
+                )));
+            }
+            self.as_ptr().copy_within(slot(at + removed)..array_end, slot(at));
+            count - removed
+        };
+        self.write_cell_count(new_count as u16);
+        Ok(())
+    }
+
+    /// Offsets of the cells in logical (key) order, read lazily from the pointer array
+    pub fn cell_pointers(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        let array_start = self.cell_pointer_array_offset();
+        (0..self.read_cell_count() as usize)
+            .map(move |idx| self.read_u16_no_offset(array_start + idx * CELL_PTR_SIZE_BYTES) as usize)
+    }
+
+    /// cell_pointers from the last cell to the first, for reverse scans
+    pub fn cell_pointers_rev(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cell_pointers().rev()
+    }
+
+    /// (logical index, content offset) of every cell, sorted by where the cell
+    /// sits in the content area rather than by its slot in the pointer array
+    pub fn cells_by_content_offset(&self) -> Vec<(usize, usize)> {
+        let mut cells: Vec<(usize, usize)> = self.cell_pointers().enumerate().collect();
+        cells.sort_by_key(|&(_, offset)| offset);
+        cells
+    }
+
+    /// Payload of the cell at idx, split into the part stored on this page and
+    /// the part that spilled to overflow pages. Table interior cells have no payload
+    /// Index interior: left child (u32) | payload size (varint) | payload | overflow page (u32)
+    /// Table leaf:     payload size (varint) | rowid (varint) | payload | overflow page (u32)
+    /// Index leaf:     payload size (varint) | payload | overflow page (u32)
+    pub fn cell_payload(&self, idx: usize, usable_size: usize) -> Result<CellPayload<'_>> {
+        let page_type = self.try_page_type()?;
+        let buf = self.buffer.as_slice();
+        let mut pos = self.cell_pointer(idx)?;
+        match page_type {
+            PageType::TableInterior => bail_corrupt_error!("table interior cells have no payload"),
+            PageType::IndexInterior => pos += 4,
+            PageType::IndexLeaf | PageType::TableLeaf => {}
+        }
+        let (payload_len, n) = read_varint(buf.get(pos..).unwrap_or_default())?;
+        pos += n;
+        if matches!(page_type, PageType::TableLeaf) {
+            let (_, n) = read_varint(buf.get(pos..).unwrap_or_default())?;
+            pos += n;
+        }
+
+        let payload_len = payload_len as usize;
+        let local_len = local_payload_len(page_type, payload_len, usable_size);
+        let Some(local) = buf.get(pos..pos + local_len) else {
+            bail_corrupt_error!("cell {idx} payload of {local_len} bytes runs past the end of the page");
+        };
+        let first_overflow_page = if local_len < payload_len {
+            match try_read_u32(buf, pos + local_len) {
+                Some(page_no) => Some(page_no),
+                None => bail_corrupt_error!("cell {idx} overflow page pointer runs past the end of the page"),
+            }
+        } else {
+            None
+        };
+        Ok(CellPayload { payload_len, local, first_overflow_page })
+    }
+
+    /// Rowid of the cell at idx on a table leaf page
+    /// Table leaf cell: payload size (varint) | rowid (varint) | payload | overflow page (u32)
+    /// The rowid lives in the cell itself, it is not one of the record columns
+    pub fn table_leaf_cell_rowid(&self, idx: usize) -> Result<i64> {
+        if !matches!(self.try_page_type()?, PageType::TableLeaf) {
+            bail_corrupt_error!("rowid requested from a page that is not a table leaf");
+        }
+        let cell_start = self.cell_pointer(idx)?;
+        let buf = self.buffer.as_slice();
+        if cell_start >= buf.len() {
+            bail_corrupt_error!("cell offset {cell_start} is past the end of the page");
+        }
+        let (_, payload_size_len) = read_varint(&buf[cell_start..])?;
+        let (rowid, _) = read_varint(&buf[cell_start + payload_size_len..])?;
+        Ok(rowid as i64)
+    }
+
     pub fn unallocated_region_start(&self) -> usize {
         let (cell_ptr_array_start, cell_ptr_array_size) = self.cell_pointer_array_offset_and_size();
         cell_ptr_array_start + cell_ptr_array_size
@@ -276,6 +827,8 @@ impl PageContent {
         self.cell_content_area() as usize - self.unallocated_region_start()
     }
 
+    /// Start of the cell content area. A stored value of 0 can only mean 65536,
+    /// which happens on a 64KB page whose content area is still empty
     pub fn cell_content_area(&self) -> u32 {
         let offset = self.read_u16(BTREE_CELL_CONTENT_AREA);
         if offset == 0 {
@@ -290,34 +843,279 @@ impl PageContent {
         self.read_u8(BTREE_FRAGMENTED_BYTES_COUNT)
     }
 
+    /// Every child of an interior page: the left child of each cell in order,
+    /// then the rightmost pointer. Leaf pages have no children
+    pub fn child_pages(&self) -> Result<Vec<u32>> {
+        if !matches!(self.try_page_type()?, PageType::TableInterior | PageType::IndexInterior) {
+            return Ok(Vec::new());
+        }
+        let buf = self.buffer.as_slice();
+        let Some(count) = try_read_u16(buf, self.offset + BTREE_CELL_COUNT) else {
+            bail_corrupt_error!("cell count runs past the end of the page");
+        };
+        let Some(rightmost) = try_read_u32(buf, self.offset + BTREE_RIGHTMOST_PTR) else {
+            bail_corrupt_error!("rightmost pointer runs past the end of the page");
+        };
+        let count = count as usize;
+        let mut children = Vec::with_capacity(count + 1);
+        for idx in 0..count {
+            // both interior cell formats start with the 4 byte left child page number
+            match try_read_u32(buf, self.cell_pointer(idx)?) {
+                Some(page_no) => children.push(page_no),
+                None => bail_corrupt_error!("cell {idx} left child pointer runs past the end of the page"),
+            }
+        }
+        children.push(rightmost);
+        Ok(children)
+    }
+
     /// Returns value of rightmost pointer i.e. page number (value) of right most key
-    pub fn rightmost_pointer(&self) -> Option<u32> {
-        match self.page_type() {
-            PageType::IndexInterior | PageType::TableInterior => Some(self.read_u32(BTREE_RIGHTMOST_PTR)),
-            PageType::IndexLeaf | PageType::TableLeaf => None,
+    /// None for leaf pages, Corrupt if the page type or pointer is past the end of the buffer
+    pub fn rightmost_pointer(&self) -> Result<Option<u32>> {
+        match self.try_page_type()? {
+            PageType::IndexInterior | PageType::TableInterior => {
+                match try_read_u32(self.buffer.as_slice(), self.offset + BTREE_RIGHTMOST_PTR) {
+                    Some(page_no) => Ok(Some(page_no)),
+                    None => bail_corrupt_error!("rightmost pointer runs past the end of the page"),
+                }
+            }
+            PageType::IndexLeaf | PageType::TableLeaf => Ok(None),
         }
     }
 
     /// Returns a pointer to the right most key
     /// Since buffer allocation guarantees page is stored contiguously in physical memory
     /// we can do valid pointer arithmetic
-    pub fn rightmost_pointer_raw(&self) -> Option<*mut u8> {
-        match self.page_type() {
-            PageType::IndexInterior | PageType::TableInterior => Some(unsafe{
-                self
-                    .as_ptr()
-                    .as_mut_ptr()
-                    .add(self.offset + BTREE_RIGHTMOST_PTR)
-            }),
-            PageType::IndexLeaf | PageType::TableLeaf => None,
+    pub fn rightmost_pointer_raw(&self) -> Result<Option<*mut u8>> {
+        match self.try_page_type()? {
+            PageType::IndexInterior | PageType::TableInterior => {
+                if self.offset + BTREE_RIGHTMOST_PTR + 4 > self.buffer.len() {
+                    bail_corrupt_error!("rightmost pointer runs past the end of the page");
+                }
+                Ok(Some(unsafe{
+                    self
+                        .as_ptr()
+                        .as_mut_ptr()
+                        .add(self.offset + BTREE_RIGHTMOST_PTR)
+                }))
+            }
+            PageType::IndexLeaf | PageType::TableLeaf => Ok(None),
+        }
+    }
+}
+
+/// Largest payload stored entirely on a page of this type, anything bigger spills
+/// Table leaves use usable_size - 35, the other types (index cells and the
+/// unused table interior case) use ((usable_size - 12) * 64 / 255) - 23
+pub fn max_local(page_type: PageType, usable_size: usize) -> usize {
+    match page_type {
+        PageType::TableLeaf => usable_size - 35,
+        PageType::TableInterior | PageType::IndexInterior | PageType::IndexLeaf => {
+            ((usable_size - 12) * 64 / 255) - 23
+        }
+    }
+}
+
+/// Least amount of an overflowing payload that is kept on the page
+pub fn min_local(usable_size: usize) -> usize {
+    ((usable_size - 12) * 32 / 255) - 23
+}
+
+/// Number of payload bytes stored on the page itself, the rest goes to overflow pages
+/// Follows SQLite: keep as much as possible on the page, but when spilling, size
+/// the local part so the overflow fills whole overflow pages (usable_size - 4 each)
+pub fn local_payload_len(page_type: PageType, payload_len: usize, usable_size: usize) -> usize {
+    let max_local = max_local(page_type, usable_size);
+    if payload_len <= max_local {
+        return payload_len;
+    }
+    let min_local = min_local(usable_size);
+    let surplus = min_local + (payload_len - min_local) % (usable_size - 4);
+    if surplus <= max_local {
+        surplus
+    } else {
+        min_local
+    }
+}
+
+/// How many cells of avg_cell_size bytes fit on an empty page
+/// Each cell also costs a 2 byte slot in the cell pointer array
+pub fn cells_per_page(page_type: PageType, avg_cell_size: usize, usable_size: usize) -> usize {
+    let header_size = match page_type {
+        PageType::IndexInterior | PageType::TableInterior => INTERIOR_PAGE_HEADER_SIZE_BYTES,
+        PageType::IndexLeaf | PageType::TableLeaf => LEAF_PAGE_HEADER_SIZE_BYTES,
+    };
+    usable_size.saturating_sub(header_size) / (avg_cell_size + CELL_PTR_SIZE_BYTES)
+}
+
+/*
+Freelist trunk page layout:
+    0..4   page number of the next trunk page, 0 if this is the last one
+    4..8   number of leaf page numbers stored on this trunk (L)
+    8..    L leaf page numbers, 4 bytes each
+Documentation: https://www.sqlite.org/fileformat.html#the_freelist
+*/
+pub const FREELIST_TRUNK_NEXT: usize = 0;
+pub const FREELIST_TRUNK_LEAF_COUNT: usize = 4;
+pub const FREELIST_TRUNK_LEAVES: usize = 8;
+
+/// Walks the freelist starting at first_trunk (0 means the freelist is empty)
+/// and returns every free page: the trunk pages themselves and their leaves
+/// read_page returns the buffer of a page given its number
+/// A trunk chain that loops back on itself is reported as Corrupt
+pub fn list_free_pages<F>(first_trunk: u32, mut read_page: F) -> Result<Vec<u32>>
+where
+    F: FnMut(u32) -> Result<Arc<Buffer>>,
+{
+    let mut free_pages = Vec::new();
+    let mut visited = HashSet::new();
+    let mut trunk = first_trunk;
+    while trunk != 0 {
+        if !visited.insert(trunk) {
+            bail_corrupt_error!("freelist trunk page {trunk} visited twice");
+        }
+        free_pages.push(trunk);
+
+        let buf = read_page(trunk)?;
+        let buf = buf.as_slice();
+        let (Some(next), Some(leaf_count)) = (
+            try_read_u32(buf, FREELIST_TRUNK_NEXT),
+            try_read_u32(buf, FREELIST_TRUNK_LEAF_COUNT),
+        ) else {
+            bail_corrupt_error!("freelist trunk page {trunk} is too short");
+        };
+        for i in 0..leaf_count as usize {
+            match try_read_u32(buf, FREELIST_TRUNK_LEAVES + i * 4) {
+                Some(leaf) => free_pages.push(leaf),
+                None => bail_corrupt_error!("freelist trunk page {trunk} claims {leaf_count} leaves"),
+            }
         }
+        trunk = next;
+    }
+    Ok(free_pages)
+}
+
+/// Byte offset of a page in the database file
+/// Page numbers are 1-based, page 0 does not exist
+pub fn page_offset(page_no: u32, page_size: usize) -> Result<u64> {
+    if page_no == 0 {
+        bail_corrupt_error!("page number 0 is invalid");
     }
+    Ok((page_no as u64 - 1) * page_size as u64)
 }
 
+/// Completions of the writes issued by write_pages_vectored, one per run
+/// The writes may finish later, check error() once every completion is done
+pub struct PageWrites {
+    pub completions: Vec<Completion>,
+    error: Rc<Cell<Option<CompletionError>>>,
+}
+
+impl PageWrites {
+    pub fn is_completed(&self) -> bool {
+        self.completions.iter().all(Completion::is_completed)
+    }
+
+    /// First failure reported by any of the writes so far
+    pub fn error(&self) -> Option<CompletionError> {
+        self.error.get()
+    }
+}
+
+/// Writes a batch of pages keyed by page number (1-based)
+/// Pages with consecutive numbers are adjacent on disk, so each run of
+/// consecutive pages is issued as a single pwritev instead of one pwrite per page
+/// Every buffer must be exactly page_size bytes, otherwise later pages in its run
+/// would land at the wrong offset. Nothing is written if a buffer or page number is invalid
+/// A write that fails before returning stops the batch and is returned as the error
+pub fn write_pages_vectored(
+    file: &dyn File,
+    page_size: usize,
+    batch: BTreeMap<u32, Arc<Buffer>>,
+) -> Result<PageWrites> {
+    if let Some((page_no, buffer)) = batch.iter().find(|(_, b)| b.len() != page_size) {
+        return Err(TursoMiniError::Misuse(format!(
+            "buffer for page {page_no} is {} bytes, expected the page size {page_size}",
+            buffer.len()
+        )));
+    }
+    // BTreeMap iterates in page number order, so only the first key can be 0
+    if batch.contains_key(&0) {
+        bail_corrupt_error!("page number 0 is invalid");
+    }
+
+    let mut runs: Vec<(u32, Vec<Arc<Buffer>>)> = Vec::new();
+    for (page_no, buffer) in batch {
+        match runs.last_mut() {
+            Some((start, run)) if *start as u64 + run.len() as u64 == page_no as u64 => run.push(buffer),
+            _ => runs.push((page_no, vec![buffer])),
+        }
+    }
+
+    let error = Rc::new(Cell::new(None));
+    let mut completions = Vec::with_capacity(runs.len());
+    for (start, run) in runs {
+        let err = error.clone();
+        let c = Completion::new_write(move |res| {
+            if let Err(e) = res {
+                // keep the first failure
+                if err.get().is_none() {
+                    err.set(Some(e));
+                }
+            }
+        });
+        completions.push(file.pwritev(page_offset(start, page_size)?, run, c)?);
+        if let Some(e) = error.get() {
+            return Err(e.into());
+        }
+    }
+    Ok(PageWrites { completions, error })
+}
+
+/// Cheap probe for whether the file at path looks like a SQLite database
+/// Only the 16 byte magic string is checked, the rest of the header is not parsed
+/// Files shorter than the magic string are reported as not a database
+pub fn is_sqlite_database(io: &dyn IO, path: &str) -> Result<bool> {
+    let file = io.open_file(path, OpenFlags::ReadOnly)?;
+    if file.size()? < SQLITE_MAGIC.len() as u64 {
+        return Ok(false);
+    }
+
+    let buf = Arc::new(Buffer::new_zeroed(SQLITE_MAGIC.len()));
+    let bytes_read = Rc::new(Cell::new(0));
+    let c = {
+        let bytes_read = bytes_read.clone();
+        Completion::new_read(buf.clone(), move |res| {
+            if let Ok((_, n)) = res {
+                bytes_read.set(n);
+            }
+        })
+    };
+    let c = file.pread(0, c)?;
+    if !c.is_completed() {
+        io.wait_for_completion(c)?;
+    }
+
+    Ok(bytes_read.get() as usize == SQLITE_MAGIC.len() && buf.as_slice() == SQLITE_MAGIC)
+}
+
+/// Panics if buf is too short, only use it on buffers whose size is already known
 pub fn read_u32(buf: &[u8], pos: usize) -> u32 {
     u32::from_be_bytes([buf[pos], buf[pos+1], buf[pos+2], buf[pos+3]])
 }
 
+/// u16 counterpart of try_read_u32, None if the 2 bytes at pos are not both in buf
+pub fn try_read_u16(buf: &[u8], pos: usize) -> Option<u16> {
+    let bytes = buf.get(pos..pos.checked_add(2)?)?;
+    Some(u16::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// read_u32 for untrusted input, None if the 4 bytes at pos are not all in buf
+pub fn try_read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    let bytes = buf.get(pos..pos.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
 /*
 SQLite uses varint for rowids and keys in B-Trees
 varints use between 1 to 9 bytes to be more space efficient
@@ -453,4 +1251,1019 @@ pub fn read_varint(buf: &[u8]) -> Result<(u64, usize)> {
         }
         None => bail_corrupt_error!("invalid varint")
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::ErrorKind, pin::Pin, sync::{Arc, Mutex}};
+
+    use pack1::U32BE;
+
+    use super::{cells_per_page, is_sqlite_database, local_payload_len, max_local, min_local, AutoVacuumMode, DatabaseHeader, JournalMode, TextEncoding, SQLITE_MAGIC, DATABASE_HEADER_SIZE, list_free_pages, page_offset, try_read_u32, write_pages_vectored, write_varint, OverflowCell, PageContent, PageSize, PageType, LEAF_PAGE_HEADER_SIZE_BYTES, INTERIOR_PAGE_HEADER_SIZE_BYTES, CELL_PTR_SIZE_BYTES};
+    use crate::{error::{CompletionError, TursoMiniError}, io::{memory::MemoryIO, Buffer, Completion, File, OpenFlags, IO}, storage::btree::offset::BTREE_CELL_CONTENT_AREA, Result};
+
+    /// File that records the vectored writes issued against it
+    /// With fail_writes set, every write completes with an I/O error instead
+    #[derive(Default)]
+    struct RecordingFile {
+        writes: Mutex<Vec<(u64, Vec<u8>)>>,
+        fail_writes: bool,
+    }
+
+    impl File for RecordingFile {
+        fn lock_file(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn unlock_file(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn pread(&self, _pos: u64, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn pwrite(&self, pos: u64, buffer: Arc<Buffer>, c: Completion) -> Result<Completion> {
+            self.pwritev(pos, vec![buffer], c)
+        }
+
+        fn sync(&self, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn truncate(&self, _len: u64, c: Completion) -> Result<Completion> {
+            c.complete(0);
+            Ok(c)
+        }
+
+        fn size(&self) -> Result<u64> {
+            Ok(0)
+        }
+
+        fn pwritev(&self, pos: u64, buffers: Vec<Arc<Buffer>>, c: Completion) -> Result<Completion> {
+            let first_bytes = buffers.iter().map(|b| b.as_slice()[0]).collect();
+            self.writes.lock().unwrap().push((pos, first_bytes));
+            if self.fail_writes {
+                c.error(CompletionError::IOError(ErrorKind::Other));
+            } else {
+                c.complete(0);
+            }
+            Ok(c)
+        }
+    }
+
+    fn new_page(size: usize, page_type: PageType) -> PageContent {
+        let page = PageContent::new(0, Arc::new(Buffer::new_zeroed(size)));
+        page.write_page_type(page_type as u8);
+        page
+    }
+
+    /// Builds a table leaf page, cells are placed from the end of the page in order
+    fn build_table_leaf(size: usize, cells: &[(u64, &[u8])]) -> PageContent {
+        let page = new_page(size, PageType::TableLeaf);
+        let mut content_start = size;
+        for (i, (rowid, payload)) in cells.iter().enumerate() {
+            let mut cell = vec![0u8; 18];
+            let n = write_varint(&mut cell, payload.len() as u64);
+            let m = write_varint(&mut cell[n..], *rowid);
+            cell.truncate(n + m);
+            cell.extend_from_slice(payload);
+
+            content_start -= cell.len();
+            page.as_ptr()[content_start..content_start + cell.len()].copy_from_slice(&cell);
+            page.write_u16_no_offset(LEAF_PAGE_HEADER_SIZE_BYTES + i * 2, content_start as u16);
+        }
+        page.write_cell_count(cells.len() as u16);
+        page.write_cell_content_area(content_start as u16);
+        page
+    }
+
+    #[test]
+    fn test_shift_cell_pointers_insert_and_delete() {
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"b"), (3, b"c")]);
+        let ptrs = |p: &PageContent| {
+            (0..p.read_cell_count() as usize).map(|i| p.cell_pointer(i).unwrap()).collect::<Vec<_>>()
+        };
+        let before = ptrs(&page);
+
+        page.shift_cell_pointers(1, 1).unwrap();
+        assert_eq!(page.read_cell_count(), 4);
+        page.write_u16_no_offset(page.cell_pointer_array_offset() + 2, 400);
+        assert_eq!(ptrs(&page), vec![before[0], 400, before[1], before[2]]);
+
+        // appending at the end moves nothing
+        page.shift_cell_pointers(4, 2).unwrap();
+        assert_eq!(page.read_cell_count(), 6);
+        assert_eq!(&ptrs(&page)[..4], &[before[0], 400, before[1], before[2]]);
+        page.shift_cell_pointers(4, -2).unwrap();
+
+        page.shift_cell_pointers(1, -1).unwrap();
+        assert_eq!(ptrs(&page), before);
+        page.shift_cell_pointers(0, -3).unwrap();
+        assert_eq!(page.read_cell_count(), 0);
+    }
+
+    #[test]
+    fn test_shift_cell_pointers_bounds() {
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        let misuse = |res: Result<()>| matches!(res, Err(TursoMiniError::Misuse(_)));
+        assert!(misuse(page.shift_cell_pointers(3, 1)));
+        assert!(misuse(page.shift_cell_pointers(1, -2)));
+
+        // array may grow right up to the content area but not into it
+        let free_slots = page.unallocated_region_size() / CELL_PTR_SIZE_BYTES;
+        assert!(misuse(page.shift_cell_pointers(0, free_slots as isize + 1)));
+        assert_eq!(page.read_cell_count(), 2);
+        page.shift_cell_pointers(2, free_slots as isize).unwrap();
+        assert_eq!(page.unallocated_region_size(), 0);
+        assert!(misuse(page.shift_cell_pointers(0, 1)));
+    }
+
+    #[test]
+    fn test_shift_cell_pointers_corrupt_header() {
+        // the cell count already puts the pointer array past the content area
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        page.write_cell_content_area(LEAF_PAGE_HEADER_SIZE_BYTES as u16 + 2);
+        assert!(matches!(page.shift_cell_pointers(0, -1), Err(TursoMiniError::Corrupt(_))));
+        assert_eq!(page.read_cell_count(), 2);
+
+        // a stored 0 reads as 65536, far past the end of a small page
+        let page = build_table_leaf(512, &[(1, b"a")]);
+        page.write_cell_content_area(0);
+        // 300 slots would move the existing pointer past the end of the buffer
+        assert!(matches!(page.shift_cell_pointers(0, 300), Err(TursoMiniError::Corrupt(_))));
+        assert_eq!(page.read_cell_count(), 1);
+    }
+
+    #[test]
+    fn test_is_empty_page() {
+        assert!(new_page(512, PageType::TableLeaf).is_empty_page());
+        assert!(!build_table_leaf(512, &[(1, b"a")]).is_empty_page());
+
+        let interior = new_page(512, PageType::TableInterior);
+        interior.write_rightmost_ptr(7);
+        assert!(interior.is_empty_page());
+        interior.write_cell_count(1);
+        assert!(!interior.is_empty_page());
+    }
+
+    #[test]
+    fn test_fragmentation_ratio() {
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        assert_eq!(page.fragmentation_ratio(512), 0.0);
+
+        // shrink the unallocated region to 10 bytes and scatter 90 bytes
+        let start = page.unallocated_region_start();
+        page.write_cell_content_area((start + 10) as u16);
+        page.write_first_freeblock((start + 10) as u16);
+        page.write_freeblock((start + 10) as u16, None, 87);
+        page.write_fragmented_bytes_count(3);
+        let ratio = page.fragmentation_ratio(512);
+        assert!((ratio - 0.9).abs() < 1e-9, "ratio was {ratio}");
+
+        // full page with no free space at all
+        let full = build_table_leaf(512, &[(1, b"a")]);
+        full.write_cell_content_area(full.unallocated_region_start() as u16);
+        assert_eq!(full.fragmentation_ratio(512), 0.0);
+
+        // corrupt header: content area inside the cell pointer array
+        let corrupt = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        corrupt.write_cell_content_area(corrupt.unallocated_region_start() as u16 - 2);
+        assert_eq!(corrupt.fragmentation_ratio(512), 0.0);
+        corrupt.write_fragmented_bytes_count(3);
+        assert_eq!(corrupt.fragmentation_ratio(512), 1.0);
+    }
+
+    #[test]
+    fn test_cell_pointers_rev() {
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"bb"), (3, b"ccc")]);
+        let forward: Vec<usize> = page.cell_pointers().collect();
+        assert_eq!(forward, (0..3).map(|i| page.cell_pointer(i).unwrap()).collect::<Vec<_>>());
+
+        let mut reverse: Vec<usize> = page.cell_pointers_rev().collect();
+        reverse.reverse();
+        assert_eq!(reverse, forward);
+        assert_eq!(new_page(512, PageType::TableLeaf).cell_pointers_rev().count(), 0);
+    }
+
+    #[test]
+    fn test_cells_by_content_offset() {
+        // build_table_leaf places later cells lower in the page
+        let page = build_table_leaf(512, &[(1, b"aa"), (2, b"bb"), (3, b"cc")]);
+        let cells = page.cells_by_content_offset();
+        assert_eq!(cells.iter().map(|&(idx, _)| idx).collect::<Vec<_>>(), vec![2, 1, 0]);
+        assert!(cells.windows(2).all(|w| w[0].1 < w[1].1));
+        for (idx, offset) in cells {
+            assert_eq!(page.cell_pointer(idx).unwrap(), offset);
+        }
+        assert!(new_page(512, PageType::TableLeaf).cells_by_content_offset().is_empty());
+    }
+
+    /// Writes a single cell holding only the local part of payload plus the overflow pointer
+    fn write_spilled_cell(page: &PageContent, prefix: &[u8], payload_len: usize, local_len: usize) {
+        let mut cell = prefix.to_vec();
+        cell.extend(vec![7u8; local_len]);
+        cell.extend_from_slice(&99u32.to_be_bytes());
+        let start = page.buffer.len() - cell.len();
+        page.as_ptr()[start..].copy_from_slice(&cell);
+        page.write_u16_no_offset(page.cell_pointer_array_offset(), start as u16);
+        page.write_cell_count(1);
+        page.write_cell_content_area(start as u16);
+        assert!(local_len < payload_len);
+    }
+
+    #[test]
+    fn test_payload_thresholds_4096() {
+        // values from https://www.sqlite.org/fileformat.html#b_tree_pages with U = 4096
+        let cases = [
+            (PageType::TableLeaf, 4061),
+            (PageType::TableInterior, 1002),
+            (PageType::IndexLeaf, 1002),
+            (PageType::IndexInterior, 1002),
+        ];
+        for (page_type, expected_max) in cases {
+            assert_eq!(max_local(page_type, 4096), expected_max, "{page_type:?}");
+            assert_eq!(local_payload_len(page_type, expected_max, 4096), expected_max, "{page_type:?}");
+            assert!(local_payload_len(page_type, expected_max + 1, 4096) < expected_max + 1, "{page_type:?}");
+        }
+        assert_eq!(min_local(4096), 489);
+    }
+
+    #[test]
+    fn test_cell_payload_without_overflow() {
+        let page = build_table_leaf(512, &[(3, b"hello")]);
+        let payload = page.cell_payload(0, 512).unwrap();
+        assert_eq!(payload.payload_len, 5);
+        assert_eq!(payload.local, b"hello");
+        assert_eq!(payload.overflow_len(), 0);
+        assert_eq!(payload.first_overflow_page, None);
+    }
+
+    #[test]
+    fn test_cell_payload_with_overflow() {
+        let usable_size = 512;
+        let payload_len = 1000;
+
+        let page = new_page(usable_size, PageType::TableLeaf);
+        let local_len = local_payload_len(PageType::TableLeaf, payload_len, usable_size);
+        let mut prefix = vec![0u8; 18];
+        let n = write_varint(&mut prefix, payload_len as u64);
+        let m = write_varint(&mut prefix[n..], 1);
+        prefix.truncate(n + m);
+        write_spilled_cell(&page, &prefix, payload_len, local_len);
+
+        let payload = page.cell_payload(0, usable_size).unwrap();
+        assert_eq!(payload.payload_len, payload_len);
+        assert_eq!(payload.local.len(), local_len);
+        assert_eq!(payload.overflow_len(), payload_len - local_len);
+        assert_eq!(payload.first_overflow_page, Some(99));
+
+        // index interior cells carry a 4 byte left child before the payload size
+        let page = new_page(usable_size, PageType::IndexInterior);
+        let local_len = local_payload_len(PageType::IndexInterior, payload_len, usable_size);
+        let mut prefix = 5u32.to_be_bytes().to_vec();
+        let mut size = [0u8; 9];
+        let n = write_varint(&mut size, payload_len as u64);
+        prefix.extend_from_slice(&size[..n]);
+        write_spilled_cell(&page, &prefix, payload_len, local_len);
+
+        let payload = page.cell_payload(0, usable_size).unwrap();
+        assert_eq!(payload.local.len(), local_len);
+        assert_eq!(payload.overflow_len(), payload_len - local_len);
+        assert_eq!(payload.first_overflow_page, Some(99));
+
+        let interior = new_page(usable_size, PageType::TableInterior);
+        interior.write_cell_count(1);
+        assert!(matches!(interior.cell_payload(0, usable_size), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_child_pages() {
+        let page = new_page(512, PageType::TableInterior);
+        // table interior cell: left child (u32) | rowid (varint)
+        for (i, (child, rowid)) in [(3u32, 10u8), (4, 20), (6, 30)].into_iter().enumerate() {
+            let start = 512 - (i + 1) * 5;
+            page.as_ptr()[start..start + 4].copy_from_slice(&child.to_be_bytes());
+            page.as_ptr()[start + 4] = rowid;
+            page.write_u16_no_offset(INTERIOR_PAGE_HEADER_SIZE_BYTES + i * 2, start as u16);
+        }
+        page.write_cell_count(3);
+        page.write_cell_content_area(512 - 15);
+        page.write_rightmost_ptr(9);
+        assert_eq!(page.child_pages().unwrap(), vec![3, 4, 6, 9]);
+
+        let empty = new_page(512, PageType::IndexInterior);
+        empty.write_rightmost_ptr(2);
+        assert_eq!(empty.child_pages().unwrap(), vec![2]);
+
+        assert!(build_table_leaf(512, &[(1, b"a")]).child_pages().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_child_pages_short_buffer() {
+        // the interior header is 12 bytes, cut it off before and inside the rightmost pointer
+        for len in [1, 4, 10] {
+            let page = PageContent::new(0, Arc::new(Buffer::new_zeroed(len)));
+            page.write_page_type(PageType::TableInterior as u8);
+            assert!(matches!(page.child_pages(), Err(TursoMiniError::Corrupt(_))));
+        }
+
+        // complete header, but the cell pointer array is cut off
+        let page = PageContent::new(0, Arc::new(Buffer::new_zeroed(INTERIOR_PAGE_HEADER_SIZE_BYTES + 1)));
+        page.write_page_type(PageType::IndexInterior as u8);
+        page.write_cell_count(1);
+        assert!(matches!(page.child_pages(), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_can_fit_cell() {
+        let page = build_table_leaf(512, &[(1, b"a")]);
+        let free = page.unallocated_region_size();
+        assert!(page.can_fit_cell(free - CELL_PTR_SIZE_BYTES, 512));
+        assert!(!page.can_fit_cell(free - CELL_PTR_SIZE_BYTES + 1, 512));
+
+        // turning 20 unallocated bytes into a freeblock keeps the capacity the same
+        let content_area = page.cell_content_area() as u16;
+        page.write_cell_content_area(content_area - 20);
+        page.write_first_freeblock(content_area - 20);
+        page.write_freeblock(content_area - 20, None, 20);
+        assert_eq!(page.unallocated_region_size(), free - 20);
+        assert!(page.can_fit_cell(free - CELL_PTR_SIZE_BYTES, 512));
+        assert!(!page.can_fit_cell(free - CELL_PTR_SIZE_BYTES + 1, 512));
+
+        // fragments count as well
+        page.write_fragmented_bytes_count(3);
+        assert!(page.can_fit_cell(free - CELL_PTR_SIZE_BYTES + 3, 512));
+        assert!(!page.can_fit_cell(free - CELL_PTR_SIZE_BYTES + 4, 512));
+
+        // corrupt header: content area inside the cell pointer array, even with free fragments
+        let corrupt = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        corrupt.write_cell_content_area(corrupt.unallocated_region_start() as u16 - 2);
+        corrupt.write_fragmented_bytes_count(60);
+        assert!(!corrupt.can_fit_cell(0, 512));
+    }
+
+    #[test]
+    fn test_table_leaf_cell_rowid() {
+        let rowids = [1u64, 42, 300, 1 << 40];
+        let cells: Vec<(u64, &[u8])> = rowids.iter().map(|&r| (r, &b"payload"[..])).collect();
+        let page = build_table_leaf(4096, &cells);
+
+        let scanned: Vec<i64> = (0..page.read_cell_count() as usize)
+            .map(|i| page.table_leaf_cell_rowid(i).unwrap())
+            .collect();
+        assert_eq!(scanned, vec![1, 42, 300, 1 << 40]);
+    }
+
+    #[test]
+    fn test_table_leaf_cell_rowid_errors() {
+        let page = build_table_leaf(4096, &[(7, b"x")]);
+        assert!(matches!(page.table_leaf_cell_rowid(1), Err(TursoMiniError::Corrupt(_))));
+
+        let empty = new_page(4096, PageType::TableLeaf);
+        assert!(matches!(empty.table_leaf_cell_rowid(0), Err(TursoMiniError::Corrupt(_))));
+
+        let interior = new_page(4096, PageType::TableInterior);
+        interior.write_cell_count(1);
+        assert!(matches!(interior.table_leaf_cell_rowid(0), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_deep_copy_is_independent() {
+        let page = new_page(4096, PageType::TableLeaf);
+        page.write_cell_count(3);
+        page.write_cell_content_area(4000);
+
+        let copy = page.deep_copy();
+        assert_eq!(copy.offset, page.offset);
+        assert!(!Arc::ptr_eq(&copy.buffer, &page.buffer));
+
+        copy.write_cell_count(7);
+        copy.write_page_type(PageType::IndexLeaf as u8);
+        copy.as_ptr()[4000] = 0xAB;
+
+        assert_eq!(page.read_cell_count(), 3);
+        assert!(matches!(page.page_type(), PageType::TableLeaf));
+        assert_eq!(page.as_ptr()[4000], 0);
+        assert_eq!(copy.read_cell_count(), 7);
+        assert_eq!(copy.cell_content_area(), 4000);
+    }
+
+    #[test]
+    fn test_overflow_cell_lookup() {
+        let mut page = new_page(4096, PageType::TableLeaf);
+        assert!(page.overflow_cell_for(0).is_none());
+
+        page.overflow_cells.push(OverflowCell { index: 2, payload: Pin::new(vec![1, 2, 3]) });
+        page.overflow_cells.push(OverflowCell { index: 5, payload: Pin::new(vec![4, 5]) });
+
+        let cell = page.overflow_cell_for(5).unwrap();
+        assert_eq!(cell.index, 5);
+        assert_eq!(&cell.payload[..], &[4, 5]);
+        assert_eq!(&page.overflow_cell_for(2).unwrap().payload[..], &[1, 2, 3]);
+        assert!(page.overflow_cell_for(3).is_none());
+
+        page.clear_overflow_cells();
+        assert!(page.overflow_cells.is_empty());
+        assert!(page.overflow_cell_for(2).is_none());
+    }
+
+    #[test]
+    fn test_try_read_u8_on_short_buffer() {
+        let page = PageContent::new(100, Arc::new(Buffer::new_zeroed(50)));
+        assert_eq!(page.try_read_u8(0), None);
+        assert!(page.maybe_page_type().is_none());
+        assert!(matches!(page.try_page_type(), Err(TursoMiniError::Corrupt(_))));
+
+        let empty = PageContent::new(0, Arc::new(Buffer::new_zeroed(0)));
+        assert!(matches!(empty.try_page_type(), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_try_page_type() {
+        let page = new_page(512, PageType::IndexInterior);
+        assert_eq!(page.try_read_u8(0), Some(PageType::IndexInterior as u8));
+        assert!(matches!(page.try_page_type(), Ok(PageType::IndexInterior)));
+
+        page.write_page_type(7);
+        assert!(matches!(page.try_page_type(), Err(TursoMiniError::Corrupt(_))));
+        assert!(page.maybe_page_type().is_none());
+    }
+
+    #[test]
+    fn test_rightmost_pointer_checks_page_type() {
+        let page = new_page(512, PageType::TableInterior);
+        page.write_rightmost_ptr(9);
+        assert_eq!(page.rightmost_pointer().unwrap(), Some(9));
+        assert!(page.rightmost_pointer_raw().unwrap().is_some());
+        assert_eq!(new_page(512, PageType::TableLeaf).rightmost_pointer().unwrap(), None);
+
+        let empty = PageContent::new(0, Arc::new(Buffer::new_zeroed(0)));
+        assert!(matches!(empty.rightmost_pointer(), Err(TursoMiniError::Corrupt(_))));
+        assert!(matches!(empty.rightmost_pointer_raw(), Err(TursoMiniError::Corrupt(_))));
+
+        // interior page cut off inside the rightmost pointer
+        let short = PageContent::new(0, Arc::new(Buffer::new_zeroed(10)));
+        short.write_page_type(PageType::IndexInterior as u8);
+        assert!(matches!(short.rightmost_pointer(), Err(TursoMiniError::Corrupt(_))));
+        assert!(matches!(short.rightmost_pointer_raw(), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_cells_per_page() {
+        // (4096 - 8) / (30 + 2)
+        assert_eq!(cells_per_page(PageType::TableLeaf, 30, 4096), 127);
+        assert_eq!(cells_per_page(PageType::IndexLeaf, 100, 4096), 40);
+        // table interior cells are a 4 byte child pointer plus a rowid varint
+        assert_eq!(cells_per_page(PageType::TableInterior, 6, 4096), 510);
+        assert_eq!(cells_per_page(PageType::IndexInterior, 100, 4096), 40);
+        assert_eq!(cells_per_page(PageType::TableLeaf, 4096, 4096), 0);
+        assert_eq!(cells_per_page(PageType::TableInterior, 10, 8), 0);
+    }
+
+    fn trunk_page(next: u32, leaves: &[u32]) -> Arc<Buffer> {
+        let buf = Buffer::new_zeroed(512);
+        let data = buf.as_mut_slice();
+        data[0..4].copy_from_slice(&next.to_be_bytes());
+        data[4..8].copy_from_slice(&(leaves.len() as u32).to_be_bytes());
+        for (i, leaf) in leaves.iter().enumerate() {
+            data[8 + i * 4..12 + i * 4].copy_from_slice(&leaf.to_be_bytes());
+        }
+        Arc::new(buf)
+    }
+
+    #[test]
+    fn test_list_free_pages() {
+        let mut pages = BTreeMap::new();
+        pages.insert(3, trunk_page(8, &[4, 5]));
+        pages.insert(8, trunk_page(0, &[9]));
+
+        let mut free = list_free_pages(3, |n| Ok(pages[&n].clone())).unwrap();
+        free.sort();
+        assert_eq!(free, vec![3, 4, 5, 8, 9]);
+
+        assert!(list_free_pages(0, |_| unreachable!()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_list_free_pages_detects_cycle() {
+        let mut pages = BTreeMap::new();
+        pages.insert(3, trunk_page(8, &[4]));
+        pages.insert(8, trunk_page(3, &[]));
+        assert!(matches!(
+            list_free_pages(3, |n| Ok(pages[&n].clone())),
+            Err(TursoMiniError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_list_free_pages_rejects_bad_leaf_count() {
+        let page = trunk_page(0, &[]);
+        page.as_mut_slice()[4..8].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(matches!(
+            list_free_pages(2, |_| Ok(page.clone())),
+            Err(TursoMiniError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_page_hexdump() {
+        let page = new_page(32, PageType::TableLeaf);
+        page.write_cell_count(2);
+        let first_line = page.hexdump().lines().next().unwrap().to_string();
+        assert_eq!(
+            first_line,
+            "00000000  0d 00 00 00 02 00 00 00  00 00 00 00 00 00 00 00  |................|"
+        );
+        assert_eq!(page.hexdump().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_sqlite_magic() {
+        assert_eq!(SQLITE_MAGIC.len(), 16);
+        assert_eq!(SQLITE_MAGIC, b"SQLite format 3\0");
+        assert_eq!(&DatabaseHeader::default().as_bytes()[..16], SQLITE_MAGIC);
+    }
+
+    #[test]
+    fn test_database_header_round_trip() {
+        let header = DatabaseHeader::default();
+        let mut buf = vec![0u8; 4096];
+        header.write_to(&mut buf);
+        assert_eq!(&buf[..16], b"SQLite format 3\0");
+        assert_eq!(&buf[16..18], &4096u16.to_be_bytes());
+        assert_eq!(&buf[56..60], &1u32.to_be_bytes());
+
+        let parsed = DatabaseHeader::from_bytes(&buf).unwrap();
+        assert_eq!(parsed.as_bytes(), header.as_bytes());
+        assert_eq!(parsed.page_size.get(), 4096);
+    }
+
+    #[test]
+    fn test_database_header_text_encoding() {
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        DatabaseHeader::default().write_to(&mut buf);
+        buf[56..60].copy_from_slice(&2u32.to_be_bytes());
+        let header = DatabaseHeader::from_bytes(&buf).unwrap();
+        assert_eq!(header.text_encoding(), TextEncoding::Utf16Le);
+
+        buf[56..60].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().text_encoding(), TextEncoding::Utf16Be);
+
+        // not set yet
+        buf[56..60].copy_from_slice(&0u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().text_encoding(), TextEncoding::Utf8);
+
+        buf[56..60].copy_from_slice(&4u32.to_be_bytes());
+        assert!(matches!(DatabaseHeader::from_bytes(&buf), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_database_header_journal_mode() {
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        DatabaseHeader::default().write_to(&mut buf);
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().journal_mode(), JournalMode::Rollback);
+
+        buf[18] = 2;
+        buf[19] = 2;
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().journal_mode(), JournalMode::Wal);
+    }
+
+    #[test]
+    fn test_database_header_auto_vacuum() {
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        DatabaseHeader::default().write_to(&mut buf);
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().auto_vacuum(), AutoVacuumMode::None);
+
+        buf[52..56].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().auto_vacuum(), AutoVacuumMode::Full);
+
+        buf[64..68].copy_from_slice(&1u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().auto_vacuum(), AutoVacuumMode::Incremental);
+
+        // the incremental flag alone does nothing when auto-vacuum is off
+        buf[52..56].copy_from_slice(&0u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().auto_vacuum(), AutoVacuumMode::None);
+    }
+
+    #[test]
+    fn test_database_header_version_fields() {
+        let mut image = vec![0u8; 512];
+        DatabaseHeader::default().write_to(&mut image);
+        image[92..96].copy_from_slice(&7u32.to_be_bytes());
+        image[96..100].copy_from_slice(&3045001u32.to_be_bytes());
+
+        let header = DatabaseHeader::from_bytes(&image).unwrap();
+        assert_eq!(header.sqlite_version(), 3045001);
+        assert_eq!(header.version_valid_for(), 7);
+    }
+
+    #[test]
+    fn test_database_header_freelist_fields() {
+        let mut header = DatabaseHeader::default();
+        assert_eq!(header.freelist_head_page(), 0);
+        assert_eq!(header.freelist_page_count(), 0);
+
+        header.set_freelist_head_page(5);
+        header.set_freelist_page_count(12);
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        header.write_to(&mut buf);
+        assert_eq!(&buf[32..36], &5u32.to_be_bytes());
+        assert_eq!(&buf[36..40], &12u32.to_be_bytes());
+
+        let header = DatabaseHeader::from_bytes(&buf).unwrap();
+        assert_eq!(header.freelist_head_page(), 5);
+        assert_eq!(header.freelist_page_count(), 12);
+    }
+
+    #[test]
+    fn test_database_header_schema_cookie() {
+        let mut header = DatabaseHeader::default();
+        assert_eq!(header.schema_cookie(), 0);
+
+        // simulated CREATE TABLE
+        header.bump_schema_cookie();
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        header.write_to(&mut buf);
+        assert_eq!(&buf[40..44], &1u32.to_be_bytes());
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().schema_cookie(), 1);
+
+        header.set_schema_cookie(u32::MAX);
+        header.bump_schema_cookie();
+        assert_eq!(header.schema_cookie(), 0);
+    }
+
+    #[test]
+    fn test_database_header_user_version_and_application_id() {
+        let mut header = DatabaseHeader::default();
+        assert_eq!(header.user_version(), 0);
+        assert_eq!(header.application_id(), 0);
+
+        header.set_user_version(-3);
+        header.set_application_id(0x0f055112);
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        header.write_to(&mut buf);
+        assert_eq!(&buf[60..64], &(-3i32).to_be_bytes());
+        assert_eq!(&buf[68..72], &0x0f055112i32.to_be_bytes());
+
+        let header = DatabaseHeader::from_bytes(&buf).unwrap();
+        assert_eq!(header.user_version(), -3);
+        assert_eq!(header.application_id(), 0x0f055112);
+    }
+
+    #[test]
+    fn test_database_header_page_count() {
+        let mut header = DatabaseHeader {
+            database_size: U32BE::new(10),
+            change_counter: U32BE::new(5),
+            version_valid_for: U32BE::new(5),
+            ..Default::default()
+        };
+        assert_eq!(header.page_count(4096 * 3), 10);
+
+        // stale: a legacy writer bumped the change counter without updating the size
+        header.change_counter = U32BE::new(6);
+        assert_eq!(header.page_count(4096 * 3), 3);
+        assert_eq!(header.page_count(4096 * 3 + 100), 4);
+
+        header.change_counter = U32BE::new(5);
+        header.database_size = U32BE::new(0);
+        assert_eq!(header.page_count(4096 * 7), 7);
+    }
+
+    #[test]
+    fn test_database_header_rejects_invalid() {
+        let mut buf = [0u8; DATABASE_HEADER_SIZE];
+        DatabaseHeader::default().write_to(&mut buf);
+        assert!(matches!(DatabaseHeader::from_bytes(&buf[..99]), Err(TursoMiniError::Corrupt(_))));
+
+        let mut bad_magic = buf;
+        bad_magic[0] = b's';
+        assert!(matches!(DatabaseHeader::from_bytes(&bad_magic), Err(TursoMiniError::Corrupt(_))));
+
+        let mut bad_page_size = buf;
+        bad_page_size[16..18].copy_from_slice(&1000u16.to_be_bytes());
+        assert!(matches!(DatabaseHeader::from_bytes(&bad_page_size), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_write_freeblock_layout() {
+        // page 1: freeblock offsets are from the start of the page, not the header
+        let page = PageContent::new(100, Arc::new(Buffer::new_zeroed(4096)));
+        page.write_freeblock(3000, Some(3500), 40);
+        assert_eq!(page.read_freeblock(3000), (3500, 40));
+        assert_eq!(&page.as_ptr()[3000..3004], &[0x0d, 0xac, 0x00, 0x28]);
+    }
+
+    #[test]
+    fn test_validate_freeblocks_valid_chain() {
+        let page = new_page(4096, PageType::TableLeaf);
+        page.write_cell_content_area(1000);
+        assert!(page.validate_freeblocks(4096).is_ok());
+
+        page.write_first_freeblock(1000);
+        page.write_freeblock(1000, Some(2000), 100);
+        page.write_freeblock(2000, Some(4092), 8);
+        page.write_freeblock(4092, None, 4);
+        assert!(page.validate_freeblocks(4096).is_ok());
+    }
+
+    #[test]
+    fn test_validate_freeblocks_broken_chains() {
+        let page = new_page(4096, PageType::TableLeaf);
+        page.write_cell_content_area(1000);
+        page.write_first_freeblock(2000);
+
+        // out of order
+        page.write_freeblock(2000, Some(1500), 10);
+        page.write_freeblock(1500, None, 10);
+        assert!(matches!(page.validate_freeblocks(4096), Err(TursoMiniError::Corrupt(_))));
+
+        // overlapping
+        page.write_freeblock(2000, Some(2005), 10);
+        page.write_freeblock(2005, None, 10);
+        assert!(matches!(page.validate_freeblocks(4096), Err(TursoMiniError::Corrupt(_))));
+
+        // adjacent, or separated by a gap small enough to be a fragment
+        for next in [2010, 2013] {
+            page.write_freeblock(2000, Some(next), 10);
+            page.write_freeblock(next, None, 10);
+            assert!(matches!(page.validate_freeblocks(4096), Err(TursoMiniError::Corrupt(_))));
+        }
+        page.write_freeblock(2000, Some(2014), 10);
+        page.write_freeblock(2014, None, 10);
+        assert!(page.validate_freeblocks(4096).is_ok());
+
+        // too small
+        page.write_freeblock(2000, None, 3);
+        assert!(matches!(page.validate_freeblocks(4096), Err(TursoMiniError::Corrupt(_))));
+
+        // past the usable size, e.g. into reserved bytes
+        page.write_freeblock(2000, None, 2090);
+        assert!(page.validate_freeblocks(4096).is_ok());
+        assert!(matches!(page.validate_freeblocks(4080), Err(TursoMiniError::Corrupt(_))));
+
+        // in the unallocated region before the content area
+        page.write_first_freeblock(500);
+        page.write_freeblock(500, None, 10);
+        assert!(matches!(page.validate_freeblocks(4096), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_page_offset() {
+        assert_eq!(page_offset(1, 4096).unwrap(), 0);
+        assert_eq!(page_offset(2, 4096).unwrap(), 4096);
+        assert_eq!(page_offset(u32::MAX, 65536).unwrap(), (u32::MAX as u64 - 1) * 65536);
+        assert!(matches!(page_offset(0, 4096), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_write_pages_vectored_coalesces_runs() {
+        let file = RecordingFile::default();
+        let mut batch = BTreeMap::new();
+        for page_no in [7u32, 3, 2, 4] {
+            batch.insert(page_no, Arc::new(Buffer::new(vec![page_no as u8; 512])));
+        }
+
+        let writes = write_pages_vectored(&file, 512, batch).unwrap();
+        assert_eq!(writes.completions.len(), 2);
+        assert!(writes.is_completed());
+        assert!(writes.error().is_none());
+
+        let writes = file.writes.lock().unwrap();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(writes[0], (512, vec![2, 3, 4]));
+        assert_eq!(writes[1], (3072, vec![7]));
+    }
+
+    #[test]
+    fn test_write_pages_vectored_large_page_numbers() {
+        // offsets past 4GB must not be truncated
+        let file = RecordingFile::default();
+        let mut batch = BTreeMap::new();
+        batch.insert(u32::MAX, Arc::new(Buffer::new(vec![1; 4096])));
+        write_pages_vectored(&file, 4096, batch).unwrap();
+        assert_eq!(file.writes.lock().unwrap()[0].0, (u32::MAX as u64 - 1) * 4096);
+    }
+
+    #[test]
+    fn test_write_pages_vectored_rejects_page_zero() {
+        let file = RecordingFile::default();
+        let mut batch = BTreeMap::new();
+        batch.insert(0, Arc::new(Buffer::new_zeroed(512)));
+        batch.insert(1, Arc::new(Buffer::new_zeroed(512)));
+        assert!(matches!(
+            write_pages_vectored(&file, 512, batch),
+            Err(TursoMiniError::Corrupt(_))
+        ));
+        assert!(file.writes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_pages_vectored_rejects_wrong_buffer_size() {
+        let file = RecordingFile::default();
+        let mut batch = BTreeMap::new();
+        batch.insert(2, Arc::new(Buffer::new_zeroed(512)));
+        batch.insert(3, Arc::new(Buffer::new_zeroed(511)));
+        batch.insert(4, Arc::new(Buffer::new_zeroed(512)));
+        assert!(matches!(
+            write_pages_vectored(&file, 512, batch),
+            Err(TursoMiniError::Misuse(_))
+        ));
+        assert!(file.writes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_pages_vectored_surfaces_write_error() {
+        let file = RecordingFile { fail_writes: true, ..Default::default() };
+        let mut batch = BTreeMap::new();
+        for page_no in [2u32, 5] {
+            batch.insert(page_no, Arc::new(Buffer::new_zeroed(512)));
+        }
+        assert!(matches!(
+            write_pages_vectored(&file, 512, batch),
+            Err(TursoMiniError::CompletionError(CompletionError::IOError(ErrorKind::Other)))
+        ));
+        // the batch stops at the first failed run
+        assert_eq!(file.writes.lock().unwrap().len(), 1);
+    }
+
+    fn write_file(io: &MemoryIO, path: &str, data: Vec<u8>) {
+        let file = io.open_file(path, OpenFlags::Create).unwrap();
+        file.pwrite(0, Arc::new(Buffer::new(data)), Completion::noop_write()).unwrap();
+    }
+
+    #[test]
+    fn test_is_sqlite_database() {
+        let io = MemoryIO::new();
+        let mut header = b"SQLite format 3\0".to_vec();
+        header.resize(100, 0);
+        header[16..18].copy_from_slice(&4096u16.to_be_bytes());
+        write_file(&io, "test.db", header);
+        assert!(is_sqlite_database(&io, "test.db").unwrap());
+
+        let random: Vec<u8> = (0..100u32).map(|i| (i * 37 % 251) as u8).collect();
+        write_file(&io, "random.bin", random);
+        assert!(!is_sqlite_database(&io, "random.bin").unwrap());
+    }
+
+    #[test]
+    fn test_is_sqlite_database_short_file() {
+        let io = MemoryIO::new();
+        write_file(&io, "short.db", b"SQLite".to_vec());
+        assert!(!is_sqlite_database(&io, "short.db").unwrap());
+
+        io.open_file("empty.db", OpenFlags::Create).unwrap();
+        assert!(!is_sqlite_database(&io, "empty.db").unwrap());
+    }
+
+    #[test]
+    fn test_try_read_u32() {
+        let buf = [0x00, 0x01, 0x02, 0x03, 0x04];
+        assert_eq!(try_read_u32(&buf, 0), Some(0x00010203));
+        assert_eq!(try_read_u32(&buf, 1), Some(0x01020304));
+        assert_eq!(try_read_u32(&buf, 2), None);
+        assert_eq!(try_read_u32(&buf[..3], 0), None);
+        assert_eq!(try_read_u32(&[], 0), None);
+        assert_eq!(try_read_u32(&buf, usize::MAX), None);
+    }
+
+    #[test]
+    fn test_page_size_is_valid() {
+        assert!(PageSize::is_valid(4096));
+        assert!(PageSize::is_valid(512));
+        assert!(PageSize::is_valid(65536));
+        assert!(!PageSize::is_valid(4095));
+        assert!(!PageSize::is_valid(4097));
+        assert!(!PageSize::is_valid(256));
+        assert!(!PageSize::is_valid(131072));
+        assert!(!PageSize::is_valid(0));
+    }
+
+    #[test]
+    fn test_page_size_all_valid() {
+        let sizes: Vec<u32> = PageSize::all_valid().collect();
+        assert_eq!(sizes, vec![512, 1024, 2048, 4096, 8192, 16384, 32768, 65536]);
+        for size in sizes {
+            assert_eq!(PageSize::new(size).unwrap().get(), size);
+        }
+    }
+
+    #[test]
+    fn test_64kb_page_size_round_trip() {
+        let page_size = PageSize::new(65536).unwrap();
+        assert_eq!(page_size.get_raw(), 1);
+        assert_eq!(page_size.get(), 65536);
+
+        let from_header = PageSize::new_from_header_u16(page_size.get_raw()).unwrap();
+        assert!(from_header == page_size);
+        assert_eq!(from_header.get(), PageSize::MAX);
+
+        // through a full header write and read, 65536 is stored as 1
+        let header = DatabaseHeader { page_size, ..DatabaseHeader::default() };
+        let mut buf = vec![0u8; DATABASE_HEADER_SIZE];
+        header.write_to(&mut buf);
+        assert_eq!(&buf[16..18], &[0x00, 0x01]);
+        assert_eq!(DatabaseHeader::from_bytes(&buf).unwrap().page_size.get(), 65536);
+    }
+
+    #[test]
+    fn test_64kb_page_empty_content_area() {
+        let page = new_page(PageSize::MAX as usize, PageType::TableLeaf);
+        page.write_cell_content_area(0);
+
+        assert_eq!(page.cell_content_area(), 65536);
+        assert_eq!(
+            page.unallocated_region_size(),
+            65536 - LEAF_PAGE_HEADER_SIZE_BYTES
+        );
+    }
+
+    #[test]
+    fn test_set_cell_content_area() {
+        let page = new_page(PageSize::MAX as usize, PageType::TableLeaf);
+        page.set_cell_content_area(65536).unwrap();
+        assert_eq!(page.read_u16(BTREE_CELL_CONTENT_AREA), 0);
+        assert_eq!(page.cell_content_area(), 65536);
+
+        page.set_cell_content_area(4096).unwrap();
+        assert_eq!(page.read_u16(BTREE_CELL_CONTENT_AREA), 4096);
+        assert_eq!(page.cell_content_area(), 4096);
+    }
+
+    #[test]
+    fn test_set_cell_content_area_rejects_out_of_range() {
+        let page = new_page(512, PageType::TableLeaf);
+        for value in [0, 513, 65536, 65537] {
+            assert!(matches!(
+                page.set_cell_content_area(value),
+                Err(TursoMiniError::Misuse(_))
+            ));
+        }
+        page.set_cell_content_area(512).unwrap();
+        assert_eq!(page.cell_content_area(), 512);
+    }
+
+    #[test]
+    fn test_set_cell_content_area_rejects_header_and_pointer_array() {
+        let page = build_table_leaf(512, &[(1, b"a"), (2, b"b")]);
+        let array_end = LEAF_PAGE_HEADER_SIZE_BYTES + 2 * CELL_PTR_SIZE_BYTES;
+        for value in [1, LEAF_PAGE_HEADER_SIZE_BYTES as u32, array_end as u32 - 1] {
+            assert!(matches!(
+                page.set_cell_content_area(value),
+                Err(TursoMiniError::Misuse(_))
+            ));
+        }
+        page.set_cell_content_area(array_end as u32).unwrap();
+        assert_eq!(page.cell_content_area(), array_end as u32);
+    }
+
+    #[test]
+    fn test_64kb_page_full_content_area() {
+        let usable_size = PageSize::MAX as usize;
+        let page = new_page(usable_size, PageType::TableLeaf);
+        // an empty 64KB page stores its content area as 0
+        page.write_cell_content_area(0);
+        assert_eq!(page.cell_content_area(), 65536);
+        page.set_cell_content_area(65536).unwrap();
+        assert_eq!(page.read_u16(BTREE_CELL_CONTENT_AREA), 0);
+
+        // fill the page with table leaf cells: payload size | rowid | payload
+        let payload: Vec<u8> = (0..200).map(|i| (i % 251) as u8).collect();
+        let mut content_start = usable_size;
+        let mut rowid = 0u64;
+        loop {
+            let mut cell = vec![0u8; 18];
+            let n = write_varint(&mut cell, payload.len() as u64);
+            let m = write_varint(&mut cell[n..], rowid + 1);
+            cell.truncate(n + m);
+            cell.extend_from_slice(&payload);
+            if !page.can_fit_cell(cell.len(), usable_size) {
+                break;
+            }
+            content_start -= cell.len();
+            page.as_ptr()[content_start..content_start + cell.len()].copy_from_slice(&cell);
+            let idx = page.read_cell_count() as usize;
+            page.shift_cell_pointers(idx, 1).unwrap();
+            let ptr_pos = page.cell_pointer_array_offset() + idx * CELL_PTR_SIZE_BYTES;
+            page.write_u16_no_offset(ptr_pos, content_start as u16);
+            page.set_cell_content_area(content_start as u32).unwrap();
+            rowid += 1;
+        }
+
+        let count = page.read_cell_count() as usize;
+        assert_eq!(count as u64, rowid);
+        assert!(count > 300);
+        assert_eq!(page.cell_content_area() as usize, content_start);
+        // less room left than one more cell (2 byte size, 2 byte rowid, payload) needs
+        assert!(page.unallocated_region_size() < 2 + 2 + payload.len() + CELL_PTR_SIZE_BYTES);
+        for idx in 0..count {
+            assert_eq!(page.table_leaf_cell_rowid(idx).unwrap(), idx as i64 + 1);
+            let cell = page.cell_payload(idx, usable_size).unwrap();
+            assert_eq!(cell.local, &payload[..]);
+            assert!(cell.first_overflow_page.is_none());
+        }
+    }
+}
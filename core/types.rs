@@ -1,13 +1,21 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::{
+    error::TursoMiniError,
+    storage::sqlite3_ondisk::{read_varint, varint_len, write_varint},
+    Result,
+};
 
 // Following line adds conditional attribute to TextSubtype when serde feature is on
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextSubtype {
     Text,
     #[cfg(feature = "json")] // only compiled with json feature
     Json,
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Text {
     pub value: Vec<u8>,
     pub subtype: TextSubtype,
@@ -39,6 +47,128 @@ impl Display for Text {
         write!(f, "{}", self.as_str())
     }
 }
+
+/// A single column value, one variant per SQLite storage class
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Text(Text),
+    Blob(Vec<u8>),
+}
+
+// Typed getters with SQLite style coercion between storage classes
+// Text and blobs convert to numbers only if the whole value (ignoring
+// surrounding whitespace) is a number, reals convert to integers by truncation
+// NULL never converts and neither does anything that doesn't fit
+impl Value {
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            Value::Float(x) => float_to_i64(*x),
+            Value::Text(t) => parse_i64(t.as_str()),
+            Value::Blob(b) => parse_i64(std::str::from_utf8(b).ok()?),
+            Value::Null => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Float(x) => Some(*x),
+            // Rust parses "nan" and "inf", which SQLite does not treat as numbers
+            Value::Text(t) => t.as_str().trim().parse().ok().filter(|x: &f64| x.is_finite()),
+            Value::Blob(b) => std::str::from_utf8(b).ok()?.trim().parse().ok().filter(|x: &f64| x.is_finite()),
+            Value::Null => None,
+        }
+    }
+
+    /// Borrows text, or a blob holding valid UTF-8
+    /// Numbers have no stored text to borrow, use to_string for them
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(t) => Some(t.as_str()),
+            Value::Blob(b) => std::str::from_utf8(b).ok(),
+            _ => None,
+        }
+    }
+
+    /// Raw bytes of a blob or text value
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Text(t) => Some(&t.value),
+            Value::Blob(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+fn float_to_i64(x: f64) -> Option<i64> {
+    // i64::MAX as f64 rounds up to 2^63, which is already out of range
+    if x.is_nan() || x < i64::MIN as f64 || x >= i64::MAX as f64 {
+        return None;
+    }
+    Some(x as i64)
+}
+
+fn parse_i64(s: &str) -> Option<i64> {
+    let s = s.trim();
+    s.parse().ok().or_else(|| float_to_i64(s.parse().ok()?))
+}
+
+// Renders values the way the sqlite3 shell prints them in list mode
+// NULL is an empty string, reals always show a fractional part and
+// blobs are shown as hex literals
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::Float(x) => {
+                if x.is_finite() && x.fract() == 0.0 && x.abs() < 1e15 {
+                    write!(f, "{x:.1}")
+                } else {
+                    write!(f, "{x}")
+                }
+            }
+            Value::Text(t) => write!(f, "{t}"),
+            Value::Blob(b) => {
+                write!(f, "x'")?;
+                for byte in b {
+                    write!(f, "{byte:02x}")?;
+                }
+                write!(f, "'")
+            }
+        }
+    }
+}
+
+/// Built-in SQLite collating sequences: https://www.sqlite.org/datatype3.html#collation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// memcmp of the raw bytes
+    #[default]
+    Binary,
+    /// Like Binary but ASCII upper case letters are folded to lower case
+    /// Non ASCII characters are compared as is, same as SQLite
+    NoCase,
+    /// Like Binary but trailing spaces are ignored
+    Rtrim,
+}
+
+pub fn compare_text(a: &str, b: &str, coll: Collation) -> Ordering {
+    match coll {
+        Collation::Binary => a.as_bytes().cmp(b.as_bytes()),
+        Collation::NoCase => {
+            let a = a.bytes().map(|c| c.to_ascii_lowercase());
+            let b = b.bytes().map(|c| c.to_ascii_lowercase());
+            a.cmp(b)
+        }
+        Collation::Rtrim => a.trim_end_matches(' ').as_bytes().cmp(b.trim_end_matches(' ').as_bytes()),
+    }
+}
+
 /* Record Format:
 Documentation: https://sqlite.org/fileformat2.html#serialtype
 
@@ -57,8 +187,10 @@ S1 = Serial Type for column 1 (varint)
 S2 = Serial Type for column 2 (varint)
 S3 = Serial Type for column 3 (varint)
 */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SerialType(u64);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SerialTypeKind {
     Null,       // 0
     I8,         // 1
@@ -74,6 +206,46 @@ pub enum SerialTypeKind {
     Blob,       // >=13 and odd
 }
 
+// Names follow the SerialType constructors, e.g. "i48" or "text(5)"
+impl Display for SerialType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind() {
+            SerialTypeKind::Null => write!(f, "null"),
+            SerialTypeKind::I8 => write!(f, "i8"),
+            SerialTypeKind::I16 => write!(f, "i16"),
+            SerialTypeKind::I24 => write!(f, "i24"),
+            SerialTypeKind::I32 => write!(f, "i32"),
+            SerialTypeKind::I48 => write!(f, "i48"),
+            SerialTypeKind::I64 => write!(f, "i64"),
+            SerialTypeKind::F64 => write!(f, "f64"),
+            SerialTypeKind::ConstInt0 => write!(f, "const_int0"),
+            SerialTypeKind::ConstInt1 => write!(f, "const_int1"),
+            SerialTypeKind::Text => write!(f, "text({})", self.size()),
+            SerialTypeKind::Blob => write!(f, "blob({})", self.size()),
+        }
+    }
+}
+
+impl SerialTypeKind {
+    /// Returns the serial type for fixed size kinds
+    /// Text and Blob have no single serial type since it encodes their length
+    pub fn fixed_serial_type(&self) -> Option<SerialType> {
+        match self {
+            SerialTypeKind::Null => Some(SerialType::NULL),
+            SerialTypeKind::I8 => Some(SerialType::I8),
+            SerialTypeKind::I16 => Some(SerialType::I16),
+            SerialTypeKind::I24 => Some(SerialType::I24),
+            SerialTypeKind::I32 => Some(SerialType::I32),
+            SerialTypeKind::I48 => Some(SerialType::I48),
+            SerialTypeKind::I64 => Some(SerialType::I64),
+            SerialTypeKind::F64 => Some(SerialType::F64),
+            SerialTypeKind::ConstInt0 => Some(SerialType::CONST_INT0),
+            SerialTypeKind::ConstInt1 => Some(SerialType::CONST_INT1),
+            SerialTypeKind::Text | SerialTypeKind::Blob => None,
+        }
+    }
+}
+
 impl SerialType {
     #[inline(always)]
     pub fn u64_is_valid_serial_type(n: u64) -> bool {
@@ -104,7 +276,7 @@ impl SerialType {
     }
 
     pub fn i24() -> Self {
-        Self::I16
+        Self::I24
     }
 
     pub fn i32() -> Self {
@@ -131,6 +303,11 @@ impl SerialType {
         Self::CONST_INT1
     }
 
+    // Same default as SQLITE_MAX_LENGTH, the largest string or blob SQLite accepts
+    pub const MAX_LENGTH: u64 = 1_000_000_000;
+
+    // blob and text are infallible and meant for sizes known to be small
+    // Use try_blob/try_text for sizes coming from user input or disk
     pub fn blob(size: u64) -> Self {
         Self(12 + size * 2)
     }
@@ -139,6 +316,32 @@ impl SerialType {
         Self(13 + size * 2)
     }
 
+    pub fn try_blob(size: u64) -> Result<Self> {
+        Self::try_blob_with_limit(size, Self::MAX_LENGTH)
+    }
+
+    pub fn try_text(size: u64) -> Result<Self> {
+        Self::try_text_with_limit(size, Self::MAX_LENGTH)
+    }
+
+    pub fn try_blob_with_limit(size: u64, max_len: u64) -> Result<Self> {
+        Self::checked_variable_len(12, size, max_len)
+    }
+
+    pub fn try_text_with_limit(size: u64, max_len: u64) -> Result<Self> {
+        Self::checked_variable_len(13, size, max_len)
+    }
+
+    fn checked_variable_len(base: u64, size: u64, max_len: u64) -> Result<Self> {
+        if size > max_len {
+            return Err(TursoMiniError::TooBig(format!("size {size} exceeds limit {max_len}")));
+        }
+        match size.checked_mul(2).and_then(|n| n.checked_add(base)) {
+            Some(n) => Ok(Self(n)),
+            None => Err(TursoMiniError::TooBig(format!("size {size} overflows serial type"))),
+        }
+    }
+
     pub fn kind(&self) -> SerialTypeKind {
         match self.0 {
             0 => SerialTypeKind::Null,
@@ -176,4 +379,556 @@ impl SerialType {
             SerialTypeKind::Text => (self.0 as usize - 13) / 2,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Value {
+    /// Smallest serial type that can hold the value
+    pub fn serial_type(&self) -> SerialType {
+        match self {
+            Value::Null => SerialType::NULL,
+            Value::Integer(0) => SerialType::CONST_INT0,
+            Value::Integer(1) => SerialType::CONST_INT1,
+            Value::Integer(i) => match *i {
+                -0x80..=0x7f => SerialType::I8,
+                -0x8000..=0x7fff => SerialType::I16,
+                -0x80_0000..=0x7f_ffff => SerialType::I24,
+                -0x8000_0000..=0x7fff_ffff => SerialType::I32,
+                -0x8000_0000_0000..=0x7fff_ffff_ffff => SerialType::I48,
+                _ => SerialType::I64,
+            },
+            Value::Float(_) => SerialType::F64,
+            Value::Text(t) => SerialType::text(t.value.len() as u64),
+            Value::Blob(b) => SerialType::blob(b.len() as u64),
+        }
+    }
+}
+
+/// Appends the body bytes of value, encoded with value.serial_type()
+pub fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Integer(i) => {
+            // big endian two's complement, keeping only the low bytes
+            let size = value.serial_type().size();
+            buf.extend_from_slice(&i.to_be_bytes()[8 - size..]);
+        }
+        Value::Float(x) => buf.extend_from_slice(&x.to_be_bytes()),
+        Value::Text(t) => buf.extend_from_slice(&t.value),
+        Value::Blob(b) => buf.extend_from_slice(b),
+    }
+}
+
+/// Decodes one value of serial_type from the start of buf
+/// Returns the value and the number of body bytes it used
+pub fn read_value(buf: &[u8], serial_type: SerialType) -> Result<(Value, usize)> {
+    let size = serial_type.size();
+    let Some(bytes) = buf.get(..size) else {
+        return Err(TursoMiniError::Corrupt(format!(
+            "record body too short for {serial_type}: {} bytes left",
+            buf.len()
+        )));
+    };
+    let value = match serial_type.kind() {
+        SerialTypeKind::Null => Value::Null,
+        SerialTypeKind::ConstInt0 => Value::Integer(0),
+        SerialTypeKind::ConstInt1 => Value::Integer(1),
+        SerialTypeKind::I8
+        | SerialTypeKind::I16
+        | SerialTypeKind::I24
+        | SerialTypeKind::I32
+        | SerialTypeKind::I48
+        | SerialTypeKind::I64 => {
+            // fill the missing high bytes with the sign bit
+            let fill = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+            let mut be = [fill; 8];
+            be[8 - size..].copy_from_slice(bytes);
+            Value::Integer(i64::from_be_bytes(be))
+        }
+        SerialTypeKind::F64 => Value::Float(f64::from_be_bytes(bytes.try_into().unwrap())),
+        SerialTypeKind::Text => match std::str::from_utf8(bytes) {
+            Ok(text) => Value::Text(Text::new(text)),
+            Err(_) => return Err(TursoMiniError::Corrupt("text value is not valid UTF-8".into())),
+        },
+        SerialTypeKind::Blob => Value::Blob(bytes.to_vec()),
+    };
+    Ok((value, size))
+}
+
+/// Encodes values as a record: header size, serial types, then the bodies
+pub fn write_record(values: &[Value]) -> Vec<u8> {
+    let serial_types: Vec<SerialType> = values.iter().map(Value::serial_type).collect();
+    let header_size = record_header_size(serial_types.iter().map(|st| varint_len(st.0)).sum());
+
+    let mut record = vec![0u8; header_size];
+    let mut pos = write_varint(&mut record, header_size as u64);
+    for st in &serial_types {
+        pos += write_varint(&mut record[pos..], st.0);
+    }
+    for value in values {
+        write_value(&mut record, value);
+    }
+    record
+}
+
+/// Length of write_record(values) without building it
+pub fn record_size(values: &[Value]) -> usize {
+    let (types_len, body_len) = values.iter().fold((0, 0), |(types_len, body_len), value| {
+        let st = value.serial_type();
+        (types_len + varint_len(st.0), body_len + st.size())
+    });
+    record_header_size(types_len) + body_len
+}
+
+// The header size counts its own varint, which can make the header longer
+fn record_header_size(types_len: usize) -> usize {
+    let mut header_size = types_len + 1;
+    while varint_len(header_size as u64) + types_len != header_size {
+        header_size = varint_len(header_size as u64) + types_len;
+    }
+    header_size
+}
+
+/// Decodes a record written by write_record (or SQLite) back into its values
+pub fn read_record(buf: &[u8]) -> Result<Vec<Value>> {
+    let (header_size, mut pos) = read_varint(buf)?;
+    let header_size = header_size as usize;
+    if header_size < pos || header_size > buf.len() {
+        return Err(TursoMiniError::Corrupt(format!(
+            "record header size {header_size} is invalid for a {} byte record",
+            buf.len()
+        )));
+    }
+
+    let mut serial_types = Vec::new();
+    while pos < header_size {
+        let (n, len) = read_varint(&buf[pos..header_size])?;
+        if !SerialType::u64_is_valid_serial_type(n) {
+            return Err(TursoMiniError::Corrupt(format!("invalid serial type {n}")));
+        }
+        serial_types.push(SerialType(n));
+        pos += len;
+    }
+
+    let mut values = Vec::with_capacity(serial_types.len());
+    for st in serial_types {
+        let (value, len) = read_value(&buf[pos..], st)?;
+        values.push(value);
+        pos += len;
+    }
+    Ok(values)
+}
+
+/// Decodes a row of a rowid table. The INTEGER PRIMARY KEY column at pk_col
+/// is an alias for the rowid and is stored as NULL, so the rowid is put back in its place
+pub fn read_record_with_rowid(buf: &[u8], rowid: i64, pk_col: Option<usize>) -> Result<Vec<Value>> {
+    let mut values = read_record(buf)?;
+    if let Some(col) = pk_col {
+        match values.get_mut(col) {
+            Some(value @ Value::Null) => *value = Value::Integer(rowid),
+            Some(_) => {
+                return Err(TursoMiniError::Corrupt(format!("rowid alias column {col} is not stored as NULL")));
+            }
+            None => {
+                return Err(TursoMiniError::Corrupt(format!(
+                    "rowid alias column {col} out of range, record has {} columns",
+                    values.len()
+                )));
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Splits an index record into its ncols key columns and the trailing rowid
+/// Index b-tree entries are the indexed columns followed by the rowid of the row
+pub fn decode_index_key(payload: &[u8], ncols: usize) -> Result<(Vec<Value>, i64)> {
+    let mut values = read_record(payload)?;
+    if values.len() != ncols + 1 {
+        return Err(TursoMiniError::Corrupt(format!(
+            "index record has {} columns, expected {ncols} key columns and a rowid",
+            values.len()
+        )));
+    }
+    match values.pop() {
+        Some(Value::Integer(rowid)) => Ok((values, rowid)),
+        _ => Err(TursoMiniError::Corrupt("index record does not end with an integer rowid".into())),
+    }
+}
+
+/// Builds a record one column at a time
+/// e.g. RecordBuilder::new().push_int(1).push_text("a").build()
+#[derive(Debug, Default)]
+pub struct RecordBuilder {
+    values: Vec<Value>,
+}
+
+impl RecordBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_null(mut self) -> Self {
+        self.values.push(Value::Null);
+        self
+    }
+
+    pub fn push_int(mut self, value: i64) -> Self {
+        self.values.push(Value::Integer(value));
+        self
+    }
+
+    pub fn push_real(mut self, value: f64) -> Self {
+        self.values.push(Value::Float(value));
+        self
+    }
+
+    pub fn push_text(mut self, value: &str) -> Self {
+        self.values.push(Value::Text(Text::new(value)));
+        self
+    }
+
+    pub fn push_blob(mut self, value: &[u8]) -> Self {
+        self.values.push(Value::Blob(value.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        write_record(&self.values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::{
+        compare_text, decode_index_key, read_record, read_record_with_rowid, read_value, record_size, write_record,
+        write_value, Collation, RecordBuilder, SerialType, SerialTypeKind, Text, Value,
+    };
+    use crate::error::TursoMiniError;
+
+    #[test]
+    fn test_value_as_i64() {
+        assert_eq!(Value::Text(Text::new("42")).as_i64(), Some(42));
+        assert_eq!(Value::Text(Text::new(" -7 ")).as_i64(), Some(-7));
+        assert_eq!(Value::Text(Text::new("3.9")).as_i64(), Some(3));
+        assert_eq!(Value::Text(Text::new("42abc")).as_i64(), None);
+        assert_eq!(Value::Integer(5).as_i64(), Some(5));
+        assert_eq!(Value::Float(-2.5).as_i64(), Some(-2));
+        assert_eq!(Value::Float(f64::NAN).as_i64(), None);
+        assert_eq!(Value::Float(1e300).as_i64(), None);
+        assert_eq!(Value::Blob(b"12".to_vec()).as_i64(), Some(12));
+        assert_eq!(Value::Blob(vec![0xff]).as_i64(), None);
+        assert_eq!(Value::Null.as_i64(), None);
+    }
+
+    #[test]
+    fn test_value_as_f64_str_bytes() {
+        assert_eq!(Value::Integer(3).as_f64(), Some(3.0));
+        assert_eq!(Value::Text(Text::new("1.5")).as_f64(), Some(1.5));
+        assert_eq!(Value::Text(Text::new("abc")).as_f64(), None);
+        assert_eq!(Value::Null.as_f64(), None);
+        for text in ["nan", "NaN", "inf", "-inf", "infinity"] {
+            assert_eq!(Value::Text(Text::new(text)).as_f64(), None);
+            assert_eq!(Value::Blob(text.as_bytes().to_vec()).as_f64(), None);
+        }
+
+        assert_eq!(Value::Text(Text::new("hi")).as_str(), Some("hi"));
+        assert_eq!(Value::Blob(b"hi".to_vec()).as_str(), Some("hi"));
+        assert_eq!(Value::Integer(1).as_str(), None);
+        assert_eq!(Value::Null.as_str(), None);
+
+        assert_eq!(Value::Text(Text::new("hi")).as_bytes(), Some(&b"hi"[..]));
+        assert_eq!(Value::Blob(vec![1, 2]).as_bytes(), Some(&[1u8, 2][..]));
+        assert_eq!(Value::Float(1.0).as_bytes(), None);
+        assert_eq!(Value::Null.as_bytes(), None);
+    }
+
+    #[test]
+    fn test_record_builder_round_trip() {
+        let record = RecordBuilder::new()
+            .push_null()
+            .push_int(42)
+            .push_real(2.5)
+            .push_text("hello")
+            .push_blob(&[0xde, 0xad])
+            .build();
+        assert_eq!(
+            read_record(&record).unwrap(),
+            vec![
+                Value::Null,
+                Value::Integer(42),
+                Value::Float(2.5),
+                Value::Text(Text::new("hello")),
+                Value::Blob(vec![0xde, 0xad]),
+            ]
+        );
+        // header: size, null, i8, f64, text(5), blob(2)
+        assert_eq!(&record[..6], &[6, 0, 1, 7, 23, 16]);
+        assert_eq!(record.len(), 6 + 1 + 8 + 5 + 2);
+        assert_eq!(read_record(&RecordBuilder::new().build()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_read_value_sign_extension() {
+        let int = |bytes: &[u8], st: SerialType| read_value(bytes, st).unwrap();
+        assert_eq!(int(&[0xff; 6], SerialType::i48()), (Value::Integer(-1), 6));
+        assert_eq!(int(&[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff], SerialType::i48()), (Value::Integer(0x7fff_ffff_ffff), 6));
+        assert_eq!(int(&[0x80, 0, 0, 0, 0, 0], SerialType::i48()), (Value::Integer(-0x8000_0000_0000), 6));
+        assert_eq!(int(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc], SerialType::i48()), (Value::Integer(0x1234_5678_9abc), 6));
+
+        let i24 = SerialType::i24();
+        assert_eq!(int(&[0xff, 0xff, 0xff], i24), (Value::Integer(-1), 3));
+        assert_eq!(int(&[0x80, 0x00, 0x00], i24), (Value::Integer(-0x80_0000), 3));
+        assert_eq!(int(&[0x7f, 0xff, 0xff], i24), (Value::Integer(0x7f_ffff), 3));
+
+        // only the bytes of the serial type are consumed
+        assert_eq!(int(&[0xfe, 0x01, 0x02], SerialType::i8()), (Value::Integer(-2), 1));
+    }
+
+    /// Encodes value with write_value, checks it picked serial_type and that
+    /// read_value gives back exactly the same integer from exactly those bytes
+    fn assert_int_round_trip(value: i64, serial_type: SerialType) {
+        let value = Value::Integer(value);
+        assert_eq!(value.serial_type(), serial_type, "width for {value:?}");
+        let mut buf = Vec::new();
+        write_value(&mut buf, &value);
+        assert_eq!(buf.len(), serial_type.size(), "encoded length for {value:?}");
+        assert_eq!(read_value(&buf, serial_type).unwrap(), (value, buf.len()));
+    }
+
+    #[test]
+    fn test_integer_round_trip_all_widths() {
+        let widths = [
+            (SerialType::i8(), i8::MIN as i64, i8::MAX as i64),
+            (SerialType::i16(), i16::MIN as i64, i16::MAX as i64),
+            (SerialType::i24(), -(1 << 23), (1 << 23) - 1),
+            (SerialType::i32(), i32::MIN as i64, i32::MAX as i64),
+            (SerialType::i48(), -(1 << 47), (1 << 47) - 1),
+            (SerialType::i64(), i64::MIN, i64::MAX),
+        ];
+        let mut prev_max = 1;
+        for (serial_type, min, max) in widths {
+            assert_int_round_trip(min, serial_type);
+            assert_int_round_trip(max, serial_type);
+            // one past the previous width's range needs this width
+            assert_int_round_trip(prev_max + 1, serial_type);
+            prev_max = max;
+        }
+        assert_int_round_trip(-1, SerialType::i8());
+        assert_int_round_trip(0, SerialType::const_int0());
+        assert_int_round_trip(1, SerialType::const_int1());
+    }
+
+    #[test]
+    fn test_empty_text_and_blob() {
+        assert_eq!(SerialType::text(0).size(), 0);
+        assert_eq!(SerialType::blob(0).size(), 0);
+        assert_eq!(read_value(&[], SerialType::text(0)).unwrap(), (Value::Text(Text::new("")), 0));
+        assert_eq!(read_value(&[], SerialType::blob(0)).unwrap(), (Value::Blob(vec![]), 0));
+
+        let values = vec![Value::Integer(7), Value::Text(Text::new("")), Value::Blob(vec![]), Value::Integer(300)];
+        let record = write_record(&values);
+        // header: size, i8, text(0), blob(0), i16
+        assert_eq!(&record[..5], &[5, 1, 13, 12, 2]);
+        assert_eq!(&record[5..], &[7, 0x01, 0x2c]);
+        assert_eq!(read_record(&record).unwrap(), values);
+    }
+
+    #[test]
+    fn test_read_record_with_rowid() {
+        let record = RecordBuilder::new().push_null().push_text("alice").build();
+        assert_eq!(
+            read_record_with_rowid(&record, 7, Some(0)).unwrap(),
+            vec![Value::Integer(7), Value::Text(Text::new("alice"))]
+        );
+        // no alias, the NULL is a real NULL
+        assert_eq!(
+            read_record_with_rowid(&record, 7, None).unwrap(),
+            vec![Value::Null, Value::Text(Text::new("alice"))]
+        );
+        assert!(matches!(read_record_with_rowid(&record, 7, Some(1)), Err(TursoMiniError::Corrupt(_))));
+        assert!(matches!(read_record_with_rowid(&record, 7, Some(2)), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_decode_index_key() {
+        let record = RecordBuilder::new().push_text("smith").push_int(1970).push_int(42).build();
+        let (key, rowid) = decode_index_key(&record, 2).unwrap();
+        assert_eq!(key, vec![Value::Text(Text::new("smith")), Value::Integer(1970)]);
+        assert_eq!(rowid, 42);
+
+        // wrong column count or a rowid that is not an integer
+        assert!(matches!(decode_index_key(&record, 1), Err(TursoMiniError::Corrupt(_))));
+        let record = RecordBuilder::new().push_text("smith").push_int(1970).push_null().build();
+        assert!(matches!(decode_index_key(&record, 2), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_record_with_two_byte_header_size() {
+        // 130 columns push the header size past one varint byte
+        let values = vec![Value::Null; 130];
+        let record = write_record(&values);
+        assert_eq!(&record[..2], &[0x81, 0x04]);
+        assert_eq!(record.len(), 132);
+        assert_eq!(read_record(&record).unwrap(), values);
+    }
+
+    #[test]
+    fn test_record_size_matches_write_record() {
+        // small xorshift generator so the test is deterministic without extra dependencies
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..500 {
+            let columns = (next() % 200) as usize;
+            let values: Vec<Value> = (0..columns)
+                .map(|_| {
+                    let r = next();
+                    match r % 5 {
+                        0 => Value::Null,
+                        // shift by a random amount to cover every integer width
+                        1 => Value::Integer((r as i64) >> (r % 64)),
+                        2 => Value::Float(f64::from_bits(r)),
+                        3 => Value::Text(Text::new(&"x".repeat((r % 300) as usize))),
+                        _ => Value::Blob(vec![0; (r % 300) as usize]),
+                    }
+                })
+                .collect();
+            assert_eq!(record_size(&values), write_record(&values).len());
+        }
+        assert_eq!(record_size(&[]), 1);
+    }
+
+    #[test]
+    fn test_read_record_corrupt() {
+        let record = RecordBuilder::new().push_int(1000).push_text("abc").build();
+        assert!(matches!(read_record(&record[..record.len() - 1]), Err(TursoMiniError::Corrupt(_))));
+        // header size larger than the record
+        assert!(matches!(read_record(&[9, 0]), Err(TursoMiniError::Corrupt(_))));
+        // reserved serial type 10
+        assert!(matches!(read_record(&[2, 10]), Err(TursoMiniError::Corrupt(_))));
+        // text that is not UTF-8
+        assert!(matches!(read_record(&[2, 15, 0xff]), Err(TursoMiniError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_value_display() {
+        assert_eq!(Value::Null.to_string(), "");
+        assert_eq!(Value::Integer(42).to_string(), "42");
+        assert_eq!(Value::Integer(-7).to_string(), "-7");
+        assert_eq!(Value::Float(1.0).to_string(), "1.0");
+        assert_eq!(Value::Float(-2.5).to_string(), "-2.5");
+        assert_eq!(Value::Float(0.1).to_string(), "0.1");
+        assert_eq!(Value::Text(Text::new("hello")).to_string(), "hello");
+        assert_eq!(Value::Blob(vec![0x00, 0x0a, 0xff, b'A']).to_string(), "x'000aff41'");
+        assert_eq!(Value::Blob(vec![]).to_string(), "x''");
+    }
+
+    #[test]
+    fn test_compare_text_binary() {
+        assert_eq!(compare_text("abc", "ABC", Collation::Binary), Ordering::Greater);
+        assert_eq!(compare_text("a ", "a", Collation::Binary), Ordering::Greater);
+        assert_eq!(compare_text("abc", "abc", Collation::default()), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_text_nocase() {
+        assert_eq!(compare_text("abc", "ABC", Collation::NoCase), Ordering::Equal);
+        assert_eq!(compare_text("abc", "ABD", Collation::NoCase), Ordering::Less);
+        assert_eq!(compare_text("ab", "AB ", Collation::NoCase), Ordering::Less);
+        // only ASCII is folded
+        assert_ne!(compare_text("é", "É", Collation::NoCase), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_compare_text_rtrim() {
+        assert_eq!(compare_text("a ", "a", Collation::Rtrim), Ordering::Equal);
+        assert_eq!(compare_text("a  ", "a ", Collation::Rtrim), Ordering::Equal);
+        assert_eq!(compare_text(" a", "a", Collation::Rtrim), Ordering::Less);
+        assert_eq!(compare_text("A ", "a", Collation::Rtrim), Ordering::Less);
+    }
+
+    #[test]
+    fn test_serial_type_display() {
+        assert_eq!(SerialType::null().to_string(), "null");
+        assert_eq!(SerialType::i48().to_string(), "i48");
+        assert_eq!(SerialType::f64().to_string(), "f64");
+        assert_eq!(SerialType::const_int1().to_string(), "const_int1");
+        assert_eq!(SerialType::text(5).to_string(), "text(5)");
+        assert_eq!(SerialType::blob(0).to_string(), "blob(0)");
+        assert_eq!(format!("{:?}", SerialType::i8()), "SerialType(1)");
+        assert_eq!(format!("{:?}", SerialTypeKind::Text), "Text");
+    }
+
+    #[test]
+    fn test_fixed_serial_type_round_trip() {
+        let fixed = [
+            (SerialTypeKind::Null, 0),
+            (SerialTypeKind::I8, 1),
+            (SerialTypeKind::I16, 2),
+            (SerialTypeKind::I24, 3),
+            (SerialTypeKind::I32, 4),
+            (SerialTypeKind::I48, 5),
+            (SerialTypeKind::I64, 6),
+            (SerialTypeKind::F64, 7),
+            (SerialTypeKind::ConstInt0, 8),
+            (SerialTypeKind::ConstInt1, 9),
+        ];
+        for (kind, raw) in fixed {
+            let serial_type = kind.fixed_serial_type().unwrap();
+            assert_eq!(serial_type, SerialType(raw));
+            assert_eq!(serial_type.kind(), kind);
+        }
+    }
+
+    #[test]
+    fn test_fixed_serial_type_variable_kinds() {
+        assert!(SerialTypeKind::Text.fixed_serial_type().is_none());
+        assert!(SerialTypeKind::Blob.fixed_serial_type().is_none());
+    }
+
+    #[test]
+    fn test_try_blob_and_text() {
+        let blob = SerialType::try_blob(10).unwrap();
+        assert_eq!(blob.0, 32);
+        assert_eq!(blob.size(), 10);
+
+        let text = SerialType::try_text(10).unwrap();
+        assert_eq!(text.0, 33);
+        assert_eq!(text.size(), 10);
+    }
+
+    #[test]
+    fn test_try_blob_rejects_huge_size() {
+        let size = u64::MAX - 5;
+        assert!(matches!(SerialType::try_blob(size), Err(TursoMiniError::TooBig(_))));
+        assert!(matches!(SerialType::try_text(size), Err(TursoMiniError::TooBig(_))));
+        assert!(matches!(
+            SerialType::try_blob_with_limit(size, u64::MAX),
+            Err(TursoMiniError::TooBig(_))
+        ));
+        assert!(matches!(
+            SerialType::try_text_with_limit(u64::MAX / 2, u64::MAX),
+            Err(TursoMiniError::TooBig(_))
+        ));
+    }
+
+    #[test]
+    fn test_try_blob_respects_limit() {
+        assert!(SerialType::try_blob(SerialType::MAX_LENGTH).is_ok());
+        assert!(matches!(
+            SerialType::try_blob(SerialType::MAX_LENGTH + 1),
+            Err(TursoMiniError::TooBig(_))
+        ));
+        assert!(SerialType::try_text_with_limit(100, 100).is_ok());
+        assert!(matches!(
+            SerialType::try_text_with_limit(101, 100),
+            Err(TursoMiniError::TooBig(_))
+        ));
+    }
+}